@@ -1,5 +1,70 @@
+use anyhow::anyhow;
 use enum_iterator::IntoEnumIterator;
 
+use self::builder::AtlasLayout;
+
+pub mod builder;
+
+/// Which kind of texture the block textures are sampled from, this decides what the texture
+/// coordinates of vertices mean
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureBackend {
+    /// All textures are tiles of a single 2D atlas, texture coordinates are atlas UVs
+    Atlas,
+    /// Every texture is a layer of a 2D texture array, texture coordinates are local to the
+    /// texture (and might repeat it) and carry the layer index
+    Array,
+}
+
+/// Where the block textures are sampled from, which decides the texture coordinates of the
+/// vertices. Every renderer has its own (see `Renderer::texture_layout`), meshes are built with
+/// the one of the renderer that draws them
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextureLayout {
+    backend: TextureBackend,
+    /// Texture coordinates (base, extend) of every texture in the atlas, by `layer`
+    uvs: Vec<(glam::Vec2, glam::Vec2)>,
+}
+
+impl TextureLayout {
+    /// The textures of the prebuilt `BlockTexture::SRC`
+    pub fn new(backend: TextureBackend) -> Self {
+        Self {
+            backend,
+            uvs: BlockTexture::into_enum_iter()
+                .map(|texture| (texture.base(), texture.extend()))
+                .collect(),
+        }
+    }
+
+    /// The textures of an atlas that was packed at runtime, fails if `layout` has no tile for
+    /// one of them
+    pub fn with_atlas(backend: TextureBackend, layout: &AtlasLayout) -> anyhow::Result<Self> {
+        let uvs = BlockTexture::into_enum_iter()
+            .map(|texture| {
+                layout
+                    .uv(&texture.name())
+                    .ok_or_else(|| anyhow!("atlas has no tile for {:?}", texture))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { backend, uvs })
+    }
+
+    pub fn backend(&self) -> TextureBackend {
+        self.backend
+    }
+
+    /// Texture coordinates (base, extend) of every texture in the atlas, by `layer`
+    pub fn uvs(&self) -> &[(glam::Vec2, glam::Vec2)] {
+        &self.uvs
+    }
+
+    /// Texture coordinates (base, extend) of `texture` in the atlas
+    pub fn uv(&self, texture: &BlockTexture) -> (glam::Vec2, glam::Vec2) {
+        self.uvs[texture.layer() as usize]
+    }
+}
+
 #[derive(Debug, Clone, IntoEnumIterator)]
 pub enum BlockTexture {
     BrickGrey,
@@ -89,24 +154,98 @@ pub enum BlockTexture {
     WoodRed,
 }
 
+/// One frame of an animated texture, the texture is scrolled by `offset` (in tile space, eg.
+/// wrapping around at 1) and the frame is shown for `duration` seconds
+#[derive(Debug, Clone, Copy)]
+pub struct AnimationFrame {
+    pub offset: glam::Vec2,
+    pub duration: f32,
+}
+
+const fn frame(x: f32, y: f32, duration: f32) -> AnimationFrame {
+    AnimationFrame {
+        offset: glam::const_vec2!([x, y]),
+        duration,
+    }
+}
+
+/// Water slowly drifts diagonally
+const WATER_FRAMES: [AnimationFrame; 8] = [
+    frame(0.0, 0.0, 0.25),
+    frame(0.125, 0.0625, 0.25),
+    frame(0.25, 0.125, 0.25),
+    frame(0.375, 0.1875, 0.25),
+    frame(0.5, 0.25, 0.25),
+    frame(0.625, 0.3125, 0.25),
+    frame(0.75, 0.375, 0.25),
+    frame(0.875, 0.4375, 0.25),
+];
+
+/// Lava creeps downwards and pauses every now and then
+const LAVA_FRAMES: [AnimationFrame; 6] = [
+    frame(0.0, 0.0, 0.8),
+    frame(0.0, 0.1, 0.4),
+    frame(0.0, 0.25, 0.4),
+    frame(0.0, 0.5, 0.8),
+    frame(0.0, 0.6, 0.4),
+    frame(0.0, 0.75, 0.4),
+];
+
 impl BlockTexture {
     pub const SRC: &'static str = "atlas-extruded.png";
     const EXTEND: glam::Vec2 = glam::const_vec2!([1296.0, 1440.0]);
     const TILE_EXTEND: glam::Vec2 = glam::const_vec2!([128.0, 128.0]);
     const EXTRUSION: glam::Vec2 = glam::const_vec2!([8.0, 8.0]);
 
+    /// Name of the tile image of this texture, eg. `brick_grey` for `BrickGrey`
+    pub fn name(&self) -> String {
+        let mut name = String::new();
+        for (i, c) in format!("{:?}", self).chars().enumerate() {
+            if c.is_uppercase() && i > 0 {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        }
+        name
+    }
+
+    /// Index of the texture in the texture array
+    pub fn layer(&self) -> u32 {
+        self.clone() as u32
+    }
+
+    /// Where the texture starts in the prebuilt atlas, see `TextureLayout::uv` for the one that
+    /// is in use
     pub fn base(&self) -> glam::Vec2 {
         let pos = self.pos();
         (pos.as_vec2() * (Self::TILE_EXTEND + 2.0 * Self::EXTRUSION) + Self::EXTRUSION)
             / Self::EXTEND
     }
 
+    /// Size of the texture in the prebuilt atlas
     pub fn extend(&self) -> glam::Vec2 {
         Self::TILE_EXTEND / Self::EXTEND
     }
 
-    pub fn tex_coord(&self, local_coord: glam::Vec2) -> glam::Vec2 {
-        self.base() + local_coord * self.extend()
+    /// Texture coordinate (u, v, layer) of a vertex, depending on the backend of `layout`
+    pub fn tex_coord(&self, layout: &TextureLayout, local_coord: glam::Vec2) -> glam::Vec3 {
+        match layout.backend() {
+            TextureBackend::Atlas => {
+                let (base, extend) = layout.uv(self);
+                (base + local_coord * extend).extend(0.0)
+            }
+            TextureBackend::Array => local_coord.extend(self.layer() as f32),
+        }
+    }
+
+    /// The frames of animated textures, the offsets are applied in the shader, so meshes
+    /// using these textures do not have to be rebuilt
+    pub fn animation(&self) -> Option<&'static [AnimationFrame]> {
+        match self {
+            BlockTexture::Water => Some(&WATER_FRAMES),
+            BlockTexture::Lava => Some(&LAVA_FRAMES),
+            _ => None,
+        }
     }
 
     pub const fn pos(&self) -> glam::UVec2 {
@@ -0,0 +1,394 @@
+//! Packs individual tile images into a single texture atlas, either at runtime from loaded
+//! images or ahead of time with the `atlas-packer` tool
+use anyhow::{anyhow, bail, ensure};
+
+/// An image with 8-bit RGBA pixels, stored row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// A fully transparent image
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    pub fn from_rgba(width: u32, height: u32, pixels: Vec<u8>) -> anyhow::Result<Self> {
+        ensure!(
+            pixels.len() == (width * height * 4) as usize,
+            "expected {} bytes for a {}x{} image, got {}",
+            width * height * 4,
+            width,
+            height,
+            pixels.len()
+        );
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        ((y * self.width + x) * 4) as usize
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.index(x, y);
+        [
+            self.pixels[i],
+            self.pixels[i + 1],
+            self.pixels[i + 2],
+            self.pixels[i + 3],
+        ]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        let i = self.index(x, y);
+        self.pixels[i..i + 4].copy_from_slice(&pixel);
+    }
+
+    /// Copy of the given part of the image, eg. to cut a tile out of an existing atlas
+    pub fn sub_image(&self, rect: TileRect) -> anyhow::Result<Self> {
+        ensure!(
+            rect.x + rect.width <= self.width && rect.y + rect.height <= self.height,
+            "{:?} is outside of the {}x{} image",
+            rect,
+            self.width,
+            self.height
+        );
+        let mut image = Self::new(rect.width, rect.height);
+        for y in 0..rect.height {
+            for x in 0..rect.width {
+                image.set_pixel(x, y, self.pixel(rect.x + x, rect.y + y));
+            }
+        }
+        Ok(image)
+    }
+}
+
+/// Where a tile is placed inside of an atlas (in pixels, without the extrusion)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The UV table of an atlas, eg. where each (named) tile is located
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AtlasLayout {
+    pub width: u32,
+    pub height: u32,
+    tiles: Vec<(String, TileRect)>,
+}
+
+impl AtlasLayout {
+    pub fn tile(&self, name: &str) -> Option<TileRect> {
+        self.tiles
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, rect)| *rect)
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = &(String, TileRect)> {
+        self.tiles.iter()
+    }
+
+    /// Texture coordinates of the tile as (base, extend), like `BlockTexture::base` and
+    /// `BlockTexture::extend`
+    pub fn uv(&self, name: &str) -> Option<(glam::Vec2, glam::Vec2)> {
+        let rect = self.tile(name)?;
+        let size = glam::vec2(self.width as f32, self.height as f32);
+        Some((
+            glam::vec2(rect.x as f32, rect.y as f32) / size,
+            glam::vec2(rect.width as f32, rect.height as f32) / size,
+        ))
+    }
+
+    /// The layout as text, a `<width> <height>` header followed by one
+    /// `<name> <x> <y> <width> <height>` line per tile
+    pub fn to_table(&self) -> String {
+        let mut table = format!("{} {}\n", self.width, self.height);
+        for (name, rect) in &self.tiles {
+            table += &format!(
+                "{} {} {} {} {}\n",
+                name, rect.x, rect.y, rect.width, rect.height
+            );
+        }
+        table
+    }
+
+    /// Parses a table written by `to_table`
+    pub fn from_table(table: &str) -> anyhow::Result<Self> {
+        let mut lines = table.lines().filter(|l| !l.trim().is_empty());
+        let header = lines.next().ok_or_else(|| anyhow!("empty atlas table"))?;
+        let (width, height) = match parse_numbers(header)?.as_slice() {
+            [width, height] => (*width, *height),
+            _ => bail!("invalid atlas table header: {}", header),
+        };
+
+        let tiles = lines
+            .map(|line| {
+                let (name, numbers) = line
+                    .trim()
+                    .split_once(' ')
+                    .ok_or_else(|| anyhow!("invalid atlas table line: {}", line))?;
+                match parse_numbers(numbers)?.as_slice() {
+                    [x, y, width, height] => Ok((
+                        name.to_string(),
+                        TileRect {
+                            x: *x,
+                            y: *y,
+                            width: *width,
+                            height: *height,
+                        },
+                    )),
+                    _ => bail!("invalid atlas table line: {}", line),
+                }
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            width,
+            height,
+            tiles,
+        })
+    }
+}
+
+fn parse_numbers(s: &str) -> anyhow::Result<Vec<u32>> {
+    s.split_whitespace()
+        .map(|n| {
+            n.parse()
+                .map_err(|e| anyhow!("invalid number {} in atlas table: {}", n, e))
+        })
+        .collect()
+}
+
+/// A packed atlas image together with its layout
+pub struct Atlas {
+    pub image: Image,
+    pub layout: AtlasLayout,
+}
+
+pub struct AtlasBuilder {
+    /// How many pixels the border of each tile is repeated around it, so that neither
+    /// filtering nor mipmapping bleeds neighbouring tiles into each other
+    extrusion: u32,
+    tiles: Vec<(String, Image)>,
+}
+
+impl AtlasBuilder {
+    pub fn new(extrusion: u32) -> Self {
+        Self {
+            extrusion,
+            tiles: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, image: Image) -> &mut Self {
+        self.tiles.push((name.into(), image));
+        self
+    }
+
+    /// Packs the tiles into rows ("shelves"), tallest tiles first. The atlas is as wide as the
+    /// smallest power of two that gives a roughly square result
+    pub fn build(self) -> anyhow::Result<Atlas> {
+        ensure!(!self.tiles.is_empty(), "no tiles to pack into the atlas");
+        for (i, (name, image)) in self.tiles.iter().enumerate() {
+            ensure!(
+                image.width > 0 && image.height > 0,
+                "atlas tile {} is empty",
+                name
+            );
+            ensure!(
+                self.tiles[..i].iter().all(|(n, _)| n != name),
+                "duplicate atlas tile: {}",
+                name
+            );
+        }
+
+        let padding = 2 * self.extrusion;
+        let padded = |image: &Image| (image.width + padding, image.height + padding);
+
+        let area: u32 = self
+            .tiles
+            .iter()
+            .map(|(_, image)| {
+                let (w, h) = padded(image);
+                w * h
+            })
+            .sum();
+        let widest = self
+            .tiles
+            .iter()
+            .map(|(_, image)| padded(image).0)
+            .max()
+            .unwrap_or(1);
+        let width = ((area as f32).sqrt().ceil() as u32)
+            .max(widest)
+            .next_power_of_two();
+
+        let mut order: Vec<_> = (0..self.tiles.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(self.tiles[i].1.height));
+
+        let mut positions = vec![(0, 0); self.tiles.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for &i in &order {
+            let (w, h) = padded(&self.tiles[i].1);
+            if x + w > width {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+            positions[i] = (x, y);
+            x += w;
+            shelf_height = shelf_height.max(h);
+        }
+        let height = y + shelf_height;
+
+        let mut image = Image::new(width, height);
+        let mut tiles = Vec::with_capacity(self.tiles.len());
+        for ((name, tile), (x, y)) in self.tiles.into_iter().zip(positions) {
+            // Every pixel of the padded area takes the color of the closest tile pixel
+            let (w, h) = (tile.width + padding, tile.height + padding);
+            for dy in 0..h {
+                for dx in 0..w {
+                    let sx = dx.saturating_sub(self.extrusion).min(tile.width - 1);
+                    let sy = dy.saturating_sub(self.extrusion).min(tile.height - 1);
+                    image.set_pixel(x + dx, y + dy, tile.pixel(sx, sy));
+                }
+            }
+
+            tiles.push((
+                name,
+                TileRect {
+                    x: x + self.extrusion,
+                    y: y + self.extrusion,
+                    width: tile.width,
+                    height: tile.height,
+                },
+            ));
+        }
+
+        Ok(Atlas {
+            image,
+            layout: AtlasLayout {
+                width,
+                height,
+                tiles,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atlas::{BlockTexture, TextureBackend, TextureLayout};
+    use enum_iterator::IntoEnumIterator;
+
+    /// A tile where every pixel has its own color
+    fn gradient(width: u32, height: u32) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, [x as u8, y as u8, (width + height) as u8, 255]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn packs_tiles_without_overlap() {
+        let mut builder = AtlasBuilder::new(1);
+        builder
+            .add("small", gradient(2, 2))
+            .add("wide", gradient(6, 3))
+            .add("tall", gradient(3, 5));
+        let Atlas { image, layout } = builder.build().unwrap();
+
+        // Including the extrusion, the tiles have to be inside of the atlas and apart
+        let padded: Vec<_> = layout
+            .tiles()
+            .map(|(_, r)| (r.x - 1, r.y - 1, r.x + r.width + 1, r.y + r.height + 1))
+            .collect();
+        for (i, a) in padded.iter().enumerate() {
+            assert!(a.2 <= layout.width && a.3 <= layout.height);
+            for b in &padded[i + 1..] {
+                let apart = a.2 <= b.0 || b.2 <= a.0 || a.3 <= b.1 || b.3 <= a.1;
+                assert!(apart, "{:?} overlaps {:?}", a, b);
+            }
+        }
+
+        for (name, tile) in [("small", (2, 2)), ("wide", (6, 3)), ("tall", (3, 5))] {
+            let rect = layout.tile(name).unwrap();
+            assert_eq!(image.sub_image(rect).unwrap(), gradient(tile.0, tile.1));
+        }
+    }
+
+    #[test]
+    fn extrudes_the_borders() {
+        let mut builder = AtlasBuilder::new(2);
+        builder.add("tile", gradient(3, 2));
+        let Atlas { image, layout } = builder.build().unwrap();
+        let TileRect { x, y, .. } = layout.tile("tile").unwrap();
+        let tile = gradient(3, 2);
+
+        // Corners take the color of the corner pixel, edges the one of the closest edge pixel
+        assert_eq!(image.pixel(x - 2, y - 2), tile.pixel(0, 0));
+        assert_eq!(image.pixel(x + 4, y - 1), tile.pixel(2, 0));
+        assert_eq!(image.pixel(x - 1, y + 3), tile.pixel(0, 1));
+        assert_eq!(image.pixel(x + 1, y - 2), tile.pixel(1, 0));
+        assert_eq!(image.pixel(x + 1, y + 3), tile.pixel(1, 1));
+        assert_eq!(image.pixel(x - 2, y + 1), tile.pixel(0, 1));
+        assert_eq!(image.pixel(x + 4, y), tile.pixel(2, 0));
+    }
+
+    #[test]
+    fn rejects_invalid_tiles() {
+        assert!(AtlasBuilder::new(1).build().is_err());
+
+        let mut builder = AtlasBuilder::new(1);
+        builder.add("empty", Image::new(0, 4));
+        assert!(builder.build().is_err());
+
+        let mut builder = AtlasBuilder::new(1);
+        builder.add("a", gradient(2, 2)).add("a", gradient(2, 2));
+        assert!(builder.build().is_err());
+    }
+
+    #[test]
+    fn layout_survives_the_table() {
+        let mut builder = AtlasBuilder::new(4);
+        for (i, texture) in BlockTexture::into_enum_iter().enumerate() {
+            builder.add(texture.name(), gradient(8 + i as u32 % 3, 8));
+        }
+        let layout = builder.build().unwrap().layout;
+
+        let parsed = AtlasLayout::from_table(&layout.to_table()).unwrap();
+        assert_eq!(parsed, layout);
+
+        let uvs = TextureLayout::with_atlas(TextureBackend::Atlas, &parsed).unwrap();
+        for texture in BlockTexture::into_enum_iter() {
+            assert_eq!(Some(uvs.uv(&texture)), layout.uv(&texture.name()));
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_tables() {
+        assert!(AtlasLayout::from_table("").is_err());
+        assert!(AtlasLayout::from_table("16\n").is_err());
+        assert!(AtlasLayout::from_table("16 16\nstone 0 0 8\n").is_err());
+        assert!(AtlasLayout::from_table("16 16\nstone 0 0 8 eight\n").is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! Packs a directory of tile images into an atlas (and its UV table), or splits the prebuilt
+//! atlas back into tiles.
+//!
+//! ```text
+//! atlas-packer pack <tile dir> <atlas.png> [extrusion]
+//! atlas-packer split <atlas.png> <tile dir>
+//! ```
+//!
+//! Tiles are named after the snake cased `BlockTexture` variant, eg. `brick_grey.png`. Packing
+//! writes the UV table next to the atlas (eg. `atlas.txt`).
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+use enum_iterator::IntoEnumIterator;
+use rust_404::{
+    atlas::{
+        builder::{AtlasBuilder, TileRect},
+        BlockTexture,
+    },
+    platform::png::{read_png, write_png},
+};
+
+const DEFAULT_EXTRUSION: u32 = 8;
+
+fn pack(tile_dir: &Path, atlas_path: &Path, extrusion: u32) -> anyhow::Result<()> {
+    let mut builder = AtlasBuilder::new(extrusion);
+    for texture in BlockTexture::into_enum_iter() {
+        let path = tile_dir.join(format!("{}.png", texture.name()));
+        let image =
+            read_png(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        builder.add(texture.name(), image);
+    }
+    let atlas = builder.build()?;
+
+    write_png(atlas_path, &atlas.image)?;
+    std::fs::write(atlas_path.with_extension("txt"), atlas.layout.to_table())?;
+    Ok(())
+}
+
+/// Cuts every texture out of an atlas, that uses the texture coordinates of `BlockTexture`
+fn split(atlas_path: &Path, tile_dir: &Path) -> anyhow::Result<()> {
+    let atlas =
+        read_png(atlas_path).with_context(|| format!("failed to read {}", atlas_path.display()))?;
+    let size = glam::vec2(atlas.width as f32, atlas.height as f32);
+
+    std::fs::create_dir_all(tile_dir)?;
+    for texture in BlockTexture::into_enum_iter() {
+        let base = (texture.base() * size).round();
+        let extend = (texture.extend() * size).round();
+        let tile = atlas.sub_image(TileRect {
+            x: base.x as u32,
+            y: base.y as u32,
+            width: extend.x as u32,
+            height: extend.y as u32,
+        })?;
+        write_png(&tile_dir.join(format!("{}.png", texture.name())), &tile)?;
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["pack", tile_dir, atlas, rest @ ..] => {
+            let extrusion = match rest {
+                [] => DEFAULT_EXTRUSION,
+                [extrusion] => extrusion
+                    .parse()
+                    .map_err(|e| anyhow!("invalid extrusion {}: {}", extrusion, e))?,
+                _ => bail!("too many arguments"),
+            };
+            pack(Path::new(tile_dir), Path::new(atlas), extrusion)
+        }
+        ["split", atlas, tile_dir] => split(Path::new(atlas), Path::new(tile_dir)),
+        _ => bail!(
+            "usage:\n  atlas-packer pack <tile dir> <atlas.png> [extrusion]\n  atlas-packer split <atlas.png> <tile dir>"
+        ),
+    }
+}
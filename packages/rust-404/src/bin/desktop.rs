@@ -0,0 +1,216 @@
+//! The game in a native window, eg. to iterate on it without a browser
+//!
+//! ```text
+//! cargo run --features desktop --bin desktop [public dir]
+//! ```
+//!
+//! Clicking into the window captures the mouse, escape releases it again. F2 saves a
+//! (supersampled) screenshot into the working directory and with the `dev` feature, F5 reloads
+//! the shaders from `src/render/shaders`.
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use glutin::{Api, ContextBuilder, GlRequest};
+use rust_404::{
+    atlas::TextureBackend,
+    input::{Button, InputEvent, InputManager, Key},
+    platform::{native::FileImageLoader, Surface},
+    render::Viewport,
+    Game,
+};
+use winit::{
+    dpi::LogicalSize,
+    event::{
+        DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+    },
+    event_loop::{ControlFlow, EventLoop},
+    window::{Window, WindowBuilder},
+};
+
+/// Where the images are loaded from, if no other directory is given
+const DEFAULT_PUBLIC_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../public");
+/// Screenshots are rendered at this multiple of the window size
+const SCREENSHOT_SCALE: u32 = 2;
+#[cfg(feature = "dev")]
+const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/render/shaders");
+
+fn key(code: VirtualKeyCode) -> Option<Key> {
+    match code {
+        VirtualKeyCode::W => Some(Key::W),
+        VirtualKeyCode::A => Some(Key::A),
+        VirtualKeyCode::S => Some(Key::S),
+        VirtualKeyCode::D => Some(Key::D),
+        VirtualKeyCode::E => Some(Key::E),
+        VirtualKeyCode::Q => Some(Key::Q),
+        VirtualKeyCode::Escape => Some(Key::Escape),
+        VirtualKeyCode::Space => Some(Key::Space),
+        VirtualKeyCode::LShift => Some(Key::LShift),
+        _ => None,
+    }
+}
+
+fn button(button: MouseButton) -> Option<Button> {
+    match button {
+        MouseButton::Left => Some(Button::Primary),
+        MouseButton::Middle => Some(Button::Middle),
+        MouseButton::Right => Some(Button::Secondary),
+        MouseButton::Other(_) => None,
+    }
+}
+
+fn viewport(window: &Window) -> Viewport {
+    let size = window.inner_size();
+    Viewport {
+        width: size.width.max(1),
+        height: size.height.max(1),
+        dpr: window.scale_factor() as f32,
+    }
+}
+
+/// Captures the mouse (like the pointer lock in the browser), so that it can look around
+fn grab_cursor(window: &Window, grab: bool) -> bool {
+    match window.set_cursor_grab(grab) {
+        Ok(()) => {
+            window.set_cursor_visible(!grab);
+            grab
+        }
+        Err(e) => {
+            eprintln!("failed to grab the cursor: {}", e);
+            false
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let public_dir = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_PUBLIC_DIR.to_string());
+
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("rust-404")
+        .with_inner_size(LogicalSize::new(1200.0, 800.0));
+    let context = ContextBuilder::new()
+        // The shaders are written for WebGL2, eg. OpenGL ES 3
+        .with_gl(GlRequest::Specific(Api::OpenGlEs, (3, 0)))
+        .with_depth_buffer(24)
+        .with_vsync(true)
+        .build_windowed(window, &event_loop)?;
+    let context = unsafe {
+        context
+            .make_current()
+            .map_err(|(_, e)| anyhow!("failed to make the context current: {}", e))?
+    };
+
+    let surface = Surface {
+        context: unsafe {
+            glow::Context::from_loader_function(|name| context.get_proc_address(name) as *const _)
+        },
+        framebuffer: None,
+        viewport: viewport(context.window()),
+    };
+
+    let (input, events) = InputManager::manual();
+    let mut game = futures::executor::block_on(Game::with_surface(
+        surface,
+        Box::new(FileImageLoader::new(public_dir)),
+        input,
+        None,
+        TextureBackend::Atlas,
+    ))?;
+
+    let start = Instant::now();
+    let mut last = start;
+    let mut grabbed = false;
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        // The game outlives the event loop, so sending can not fail
+        let send = |event| {
+            let _ = events.send(event);
+        };
+
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::Resized(size) => {
+                    context.resize(size);
+                    game.set_viewport(viewport(context.window()));
+                }
+                WindowEvent::Focused(false) if grabbed => {
+                    grabbed = grab_cursor(context.window(), false);
+                }
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state,
+                            virtual_keycode: Some(code),
+                            ..
+                        },
+                    ..
+                } => {
+                    if code == VirtualKeyCode::Escape && grabbed {
+                        grabbed = grab_cursor(context.window(), false);
+                    }
+                    if code == VirtualKeyCode::F2 && state == ElementState::Pressed {
+                        let secs = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |d| d.as_secs());
+                        let path = format!("screenshot-{}.png", secs);
+                        match game.save_screenshot(path.as_ref(), SCREENSHOT_SCALE) {
+                            Ok(()) => eprintln!("saved {}", path),
+                            Err(e) => eprintln!("failed to save {}: {}", path, e),
+                        }
+                    }
+                    #[cfg(feature = "dev")]
+                    if code == VirtualKeyCode::F5 && state == ElementState::Pressed {
+                        let loader = rust_404::platform::native::FileTextLoader::new(SHADER_DIR);
+                        match futures::executor::block_on(game.reload_shaders_with(&loader)) {
+                            Ok(()) => eprintln!("reloaded shaders"),
+                            Err(e) => eprintln!("failed to reload shaders: {}", e),
+                        }
+                    }
+                    if let Some(key) = key(code) {
+                        send(match state {
+                            ElementState::Pressed => InputEvent::KeyDown(key),
+                            ElementState::Released => InputEvent::KeyUp(key),
+                        });
+                    }
+                }
+                WindowEvent::MouseInput {
+                    state: ElementState::Pressed,
+                    button: pressed,
+                    ..
+                } => {
+                    // The first click only captures the mouse
+                    if !grabbed {
+                        grabbed = grab_cursor(context.window(), true);
+                    } else if let Some(button) = button(pressed) {
+                        send(InputEvent::MouseClicked(button));
+                    }
+                }
+                WindowEvent::MouseWheel { .. } => send(InputEvent::MouseScrolled),
+                _ => (),
+            },
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta: (dx, dy) },
+                ..
+            } if grabbed => send(InputEvent::MouseMoved(dx as i32, dy as i32)),
+            Event::MainEventsCleared => context.window().request_redraw(),
+            Event::RedrawRequested(_) => {
+                let now = Instant::now();
+                game.update(
+                    now.duration_since(last).as_secs_f32(),
+                    now.duration_since(start).as_secs_f32(),
+                );
+                last = now;
+
+                game.render();
+                if let Err(e) = context.swap_buffers() {
+                    eprintln!("failed to swap buffers: {}", e);
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => (),
+        }
+    });
+}
@@ -1,22 +1,41 @@
-use super::utils;
+use crate::atlas::{builder::Image, TextureBackend};
 use crate::input::InputManager;
 use crate::input::InputState;
+#[cfg(feature = "dev")]
+use crate::platform::TextLoader;
+use crate::platform::{ImageLoader, Surface};
 use crate::render::camera::Camera;
-use crate::render::camera::UP;
-use crate::render::mesh::build_selection_ring;
+use crate::render::mesh::{build_selection_ring, selection_ring_transform};
+use crate::render::post::PostEffect;
+use crate::render::resource_pack::ResourcePack;
+use crate::render::sky::Sky;
 use crate::render::ui;
 use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, Sender};
 
+use crate::render::material::STANDARD_VERTEX_SHADER;
 use crate::render::Material;
 
 use crate::render::*;
-use crate::world::World;
+use crate::world::{Facing, World};
 
 use glow::Texture;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::JsCast;
 
-use web_sys::WebGl2RenderingContext;
+#[cfg(all(target_arch = "wasm32", feature = "dev"))]
+use crate::platform::web::{show_errors, WebTextLoader};
+#[cfg(target_arch = "wasm32")]
+use {
+    super::utils,
+    crate::platform::{
+        png::encode_png,
+        web::{canvas_surface, WebImageLoader},
+    },
+    wasm_bindgen::JsCast,
+    web_sys::HtmlCanvasElement,
+};
+
+const SELECTION_FRAGMENT_CODE: &str = include_str!("render/shaders/selection.frag");
 
 #[wasm_bindgen]
 pub struct Game {
@@ -28,71 +47,81 @@ pub struct Game {
     world: World,
 
     // Rendering Stuff
-    light_dir: glam::Vec3,
+    sky: Sky,
+    /// Total elapsed time in seconds
+    time: f32,
+    /// Resized together with the viewport, if the game runs in a canvas
+    #[cfg(target_arch = "wasm32")]
+    canvas: Option<HtmlCanvasElement>,
+    /// Resource packs are loaded in the background and applied on the next update
+    resource_packs: (
+        Sender<anyhow::Result<ResourcePack>>,
+        Receiver<anyhow::Result<ResourcePack>>,
+    ),
     // UI Stuff
     selection_ring: Mesh,
-    crosshair: Texture,
+    selection_material: Material,
+    crosshair: Handle<Texture>,
 }
 
+#[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 impl Game {
     pub async fn new() -> Self {
-        utils::set_panic_hook();
-
-        let window = web_sys::window().unwrap();
-        let document = window.document().unwrap();
-        let canvas = document.get_element_by_id("canvas").unwrap();
-        let canvas: web_sys::HtmlCanvasElement =
-            canvas.dyn_into::<web_sys::HtmlCanvasElement>().unwrap();
-
-        let context = canvas
-            .get_context("webgl2")
-            .unwrap()
-            .unwrap()
-            .dyn_into::<WebGl2RenderingContext>()
-            .unwrap();
-
-        // TODO: This is only a valid function for the wasm32 target (catch that, w/o rust-analyzer sucking hard)
-        let context = glow::Context::from_webgl2_context(context);
-
-        let input =
-            InputManager::new(&window.document().unwrap()).expect("failed to create input manager");
-        let camera = Camera::new();
-
-        let renderer = Rc::new(
-            Renderer::new(context)
-                .await
-                .expect("failed to create renderer"),
-        );
-
-        // let vertices = cube(glam::Vec3::splat(1.0));
-
-        let selection_ring = renderer
-            .create_mesh(&build_selection_ring())
-            .expect("failed to create selection ring mesh");
-
-        let crosshair = renderer
-            .load_texture("crosshair.png")
-            .await
-            .expect("failed to create crosshair texture");
-
-        let world = World::new(renderer.clone());
+        Self::create(None, TextureBackend::Atlas).await
+    }
 
-        Self {
-            input,
-            input_state: Default::default(),
-            camera,
-            renderer,
+    /// Like `new`, but packs the texture atlas at startup from the individual tile images in
+    /// `tile_dir` (eg. `tile_dir/brick_grey.png`), instead of using the prebuilt one
+    pub async fn with_tiles(tile_dir: String) -> Self {
+        Self::create(Some(tile_dir), TextureBackend::Atlas).await
+    }
 
-            world,
+    /// Like `new`, but samples the block textures from a texture array instead of the atlas
+    pub async fn with_texture_array() -> Self {
+        Self::create(None, TextureBackend::Array).await
+    }
 
-            light_dir: glam::Vec3::ZERO,
+    /// Loads the atlas image at `atlas_src` together with the `manifest`, which maps every
+    /// texture name (eg. `brick_grey`) to its tile, one `<name> <x> <y> <width> <height>` line
+    /// per texture after an `<atlas width> <atlas height>` header. The block textures are
+    /// replaced once the atlas has been loaded
+    pub fn load_resource_pack(&self, atlas_src: String, manifest: String) {
+        let renderer = self.renderer.clone();
+        let sender = self.resource_packs.0.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let pack = ResourcePack::load(&renderer, &atlas_src, &manifest).await;
+            // The game might be gone already
+            let _ = sender.send(pack);
+        });
+    }
 
-            selection_ring,
-            crosshair,
+    /// Resizes the canvas to `width` x `height` CSS pixels, which are `dpr` (eg.
+    /// `window.devicePixelRatio`) physical pixels each, so that it renders at full resolution
+    pub fn resize(&mut self, width: u32, height: u32, dpr: f32) {
+        let viewport = Viewport {
+            width: ((width as f32 * dpr).round() as u32).max(1),
+            height: ((height as f32 * dpr).round() as u32).max(1),
+            dpr,
+        };
+        if let Some(canvas) = &self.canvas {
+            canvas.set_width(viewport.width);
+            canvas.set_height(viewport.height);
         }
+        self.set_viewport(viewport);
     }
 
+    /// Renders a frame and returns it as a PNG file, at `scale` times the size of the canvas
+    /// (eg. 2 for a supersampled thumbnail)
+    pub fn screenshot(&mut self, scale: u32) -> Result<Vec<u8>, JsValue> {
+        self.capture(scale)
+            .and_then(|image| encode_png(&image))
+            .map_err(|e| JsValue::from_str(&format!("failed to take screenshot: {}", e)))
+    }
+}
+
+#[wasm_bindgen]
+impl Game {
     pub fn update(&mut self, dt: f32, total: f32) {
         // Update the input
         self.input.update(
@@ -102,59 +131,222 @@ impl Game {
 
         // log!("Got dt: {}", dt);
         self.camera.update(dt, &self.input_state);
+        self.world.update(&self.camera);
+
+        // Update time of day
+        self.sky.update(total);
+        self.time = total;
 
-        // Update sun position
-        let axis = UP;
-        self.light_dir = (glam::Mat3::from_axis_angle(axis, total / 10.0) * glam::Vec3::X
-            + glam::vec3(0.0, 1.0, 0.0))
-        .normalize();
+        while let Ok(pack) = self.resource_packs.1.try_recv() {
+            match pack.and_then(|pack| self.renderer.use_resource_pack(pack)) {
+                Ok(true) => self.world.remesh_all(),
+                Ok(false) => (),
+                Err(e) => {
+                    log!("failed to load resource pack: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Enables or disables one of the post processing effects
+    pub fn set_post_effect(&self, effect: PostEffect, enabled: bool) {
+        self.renderer.set_post_effect(effect, enabled);
+    }
+
+    /// Sets the distances (in blocks) from which on chunks are drawn with less detail (and can't
+    /// be picked anymore), see `World::set_lod_distances`
+    pub fn set_lod_distances(&mut self, distances: Vec<f32>) {
+        self.world.set_lod_distances(&distances);
+    }
+
+    /// Statistics of the last frame, eg. for a debug overlay
+    pub fn stats(&self) -> RenderStats {
+        self.renderer.stats()
     }
 
     pub fn render(&mut self) {
-        let (mut task, mut frame) = self.renderer.start_frame();
-        self.world.render(&mut task);
+        let (mut task, mut frame) = self.renderer.start_frame(&self.camera);
+        self.world.render(&mut task, &self.camera);
 
         // Pick with the chunks
-        let picked = self.renderer.pick(&task, &self.camera);
-        if let Some((focused, face)) = &picked {
-            // -> if we currently pick a block, add a selection ring
-            let normal = face.normal();
-            let transform = glam::Mat4::from_translation(focused.clone() + 0.5 * normal);
-            let transform =
-                transform * glam::Mat4::from_axis_angle(UP.cross(normal), UP.angle_between(normal));
+        let world = &self.world;
+        let camera = &self.camera;
+        let picked = self.renderer.pick(&task, camera).and_then(|loc| {
+            // Figure out the face with the actual shape of the block
+            let boxes = world.boxes(loc.as_ivec3())?;
+            pick_face(camera, loc, &boxes).map(|(face, bounds)| (loc, face, bounds))
+        });
+        if let Some((focused, face, bounds)) = &picked {
+            // -> if we currently pick a block, add a selection ring around the picked face
             task.push_with_transform_and_material(
                 &self.selection_ring,
-                transform,
-                Material::Solid(glam::vec4(0.7, 0.7, 0.7, 1.0)),
+                selection_ring_transform(*focused, face, bounds),
+                self.selection_material.clone(),
             )
         }
 
         // Draw some ui at the end
+        let size = self.renderer.viewport().logical_size();
         frame.rect(
-            UiRect::from_coords(290, 190, 20, 20),
-            UiMaterial::Sprite(self.crosshair),
+            UiRect::new(0.5 * size - 10.0, glam::Vec2::splat(20.0)),
+            UiMaterial::Sprite(self.crosshair.raw()),
         );
 
+        #[cfg(feature = "dev")]
+        {
+            // A shader failed to reload, the full errors are logged (and shown on top of the
+            // page in the browser)
+            let errors = self.renderer.shader_errors();
+            if !errors.is_empty() {
+                ui::errors(&mut frame, &errors, size);
+            }
+        }
+
         ui::inventory(
             &mut frame,
-            &self.world.types,
-            &self.world.active_type,
+            self.world.types(),
+            self.world.active_type(),
             &self.renderer.get_atlas(),
+            &self.renderer.texture_layout(),
+            size,
         );
 
         self.renderer
-            .render(task, frame, &self.camera, &self.light_dir);
+            .render(task, frame, &self.camera, &self.sky, self.time);
 
         // Update the world with the last picked
-        self.world.last_picked = picked;
+        self.world.last_picked = picked.map(|(loc, face, _)| (loc, face));
+        self.world.facing = Facing::from_dir(self.camera.dir);
     }
 }
 
-impl Drop for Game {
-    fn drop(&mut self) {
-        unsafe {
-            // This is safe, since it is called inside of the drop function
-            self.renderer.destroy_mesh_ref(&self.selection_ring);
+impl Game {
+    /// Creates the game on any platform, eg. `input` receives the events of the platform and
+    /// the atlas is packed from the tiles in `tile_dir`, if there is one
+    pub async fn with_surface(
+        surface: Surface,
+        images: Box<dyn ImageLoader>,
+        input: InputManager,
+        tile_dir: Option<&str>,
+        backend: TextureBackend,
+    ) -> anyhow::Result<Self> {
+        let mut camera = Camera::new();
+        camera.set_aspect(surface.viewport.aspect());
+
+        let renderer = Rc::new(Renderer::new(surface, backend, images).await?);
+
+        // Has to happen before any meshes are built
+        if let Some(tile_dir) = tile_dir {
+            renderer.load_tile_atlas(tile_dir).await?;
         }
+
+        // let vertices = cube(glam::Vec3::splat(1.0), &self.renderer.texture_layout());
+
+        let selection_ring = renderer.create_mesh(&build_selection_ring())?;
+        let selection_material = Material::new(
+            renderer.create_program(STANDARD_VERTEX_SHADER, SELECTION_FRAGMENT_CODE)?,
+        )
+        .with("glow_color", glam::vec4(0.7, 0.7, 0.7, 1.0));
+
+        let crosshair = renderer.load_texture("crosshair.png").await?;
+
+        let world = World::new(renderer.clone());
+
+        Ok(Self {
+            input,
+            input_state: Default::default(),
+            camera,
+            renderer,
+
+            world,
+
+            sky: Sky::new(),
+            time: 0.0,
+            #[cfg(target_arch = "wasm32")]
+            canvas: None,
+            resource_packs: channel(),
+
+            selection_ring,
+            selection_material,
+            crosshair,
+        })
+    }
+
+    /// Renders at the size of `viewport` from now on, eg. after the window has been resized
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        self.renderer.resize(viewport);
+        self.camera.set_aspect(viewport.aspect());
+    }
+
+    /// Loads the shaders with `loader` and recompiles them, see `Renderer::reload_shaders`
+    #[cfg(feature = "dev")]
+    pub async fn reload_shaders_with(&self, loader: &dyn TextLoader) -> anyhow::Result<()> {
+        let sources = Renderer::fetch_shaders(loader).await?;
+        self.renderer.reload_shaders(&sources);
+        Ok(())
+    }
+
+    /// Renders a frame and reads it back, at `scale` times the size of the viewport
+    pub fn capture(&mut self, scale: u32) -> anyhow::Result<Image> {
+        let renderer = self.renderer.clone();
+        renderer.capture(scale, |_| self.render())
+    }
+
+    /// Like `screenshot` in the browser, but writes the PNG file to `path`
+    #[cfg(feature = "native")]
+    pub fn save_screenshot(&mut self, path: &std::path::Path, scale: u32) -> anyhow::Result<()> {
+        crate::platform::png::write_png(path, &self.capture(scale)?)
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "dev"))]
+#[wasm_bindgen]
+impl Game {
+    /// Fetches the shaders from `root` (eg. `/packages/rust-404/src/render/shaders` on the dev
+    /// server) and recompiles them in the background. Shaders that fail to compile keep their
+    /// previous version and the errors are shown on top of the page
+    pub fn reload_shaders(&self, root: String) {
+        let renderer = self.renderer.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            match Renderer::fetch_shaders(&WebTextLoader::new(root)).await {
+                Ok(sources) => {
+                    renderer.reload_shaders(&sources);
+                    show_errors(&renderer.shader_errors());
+                }
+                Err(e) => {
+                    log!("failed to fetch shaders: {}", e);
+                    show_errors(&[format!("failed to fetch shaders: {}", e)]);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Game {
+    async fn create(tile_dir: Option<String>, backend: TextureBackend) -> Self {
+        utils::set_panic_hook();
+
+        let window = web_sys::window().unwrap();
+        let document = window.document().unwrap();
+        let canvas = document.get_element_by_id("canvas").unwrap();
+        let canvas: HtmlCanvasElement = canvas.dyn_into::<HtmlCanvasElement>().unwrap();
+
+        let surface = canvas_surface(&canvas).expect("failed to create webgl2 context");
+
+        let input =
+            InputManager::new(&window.document().unwrap()).expect("failed to create input manager");
+
+        let mut game = Self::with_surface(
+            surface,
+            Box::new(WebImageLoader),
+            input,
+            tile_dir.as_deref(),
+            backend,
+        )
+        .await
+        .expect("failed to create game");
+        game.canvas = Some(canvas);
+        game
     }
 }
@@ -10,7 +10,7 @@ pub use state::*;
 
 use web_sys::EventTarget;
 
-use std::sync::mpsc::{channel, Receiver};
+use std::sync::mpsc::{channel, Receiver, Sender};
 
 pub struct InputManager {
     receiver: Receiver<InputEvent>,
@@ -76,6 +76,18 @@ impl InputManager {
         })
     }
 
+    /// Without any callbacks, the events are sent in through the returned sender instead (eg.
+    /// by a native event loop)
+    pub fn manual() -> (Self, Sender<InputEvent>) {
+        let (sender, receiver) = channel();
+        let manager = Self {
+            receiver,
+            _keyboard: Vec::new(),
+            _mouse: Vec::new(),
+        };
+        (manager, sender)
+    }
+
     fn poll(&self) -> impl Iterator<Item = InputEvent> + '_ {
         self.receiver.try_iter()
     }
@@ -3,11 +3,17 @@
 #[macro_use]
 mod utils;
 pub mod atlas;
+// Natively the game and the renderer are only built with the `native` feature
+#[cfg(any(target_arch = "wasm32", feature = "native"))]
 mod game;
 pub mod input;
-mod render;
-mod world;
+pub mod platform;
+#[cfg(any(target_arch = "wasm32", feature = "native"))]
+pub mod render;
+#[cfg(any(target_arch = "wasm32", feature = "native"))]
+pub mod world;
 
+#[cfg(any(target_arch = "wasm32", feature = "native"))]
 pub use game::*;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
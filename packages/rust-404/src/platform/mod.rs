@@ -0,0 +1,33 @@
+//! Everything the renderer needs from the platform it runs on, eg. a GL context (of a WebGL2
+//! canvas in the browser or a surfaceless EGL context natively) and a way to load images
+use futures::future::LocalBoxFuture;
+
+use crate::atlas::builder::Image;
+
+#[cfg(feature = "native")]
+pub mod native;
+pub mod png;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
+
+/// Loads and decodes images, eg. the texture atlas or the crosshair
+pub trait ImageLoader {
+    /// `src` is relative to the platform's root, eg. an url in the browser or a path natively
+    fn load<'a>(&'a self, src: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Image>>;
+}
+
+/// Loads text files, eg. the shader sources to reload them at runtime
+#[cfg(feature = "dev")]
+pub trait TextLoader {
+    /// `src` is relative to the loader's root, eg. an url or a directory
+    fn load<'a>(&'a self, src: &'a str) -> LocalBoxFuture<'a, anyhow::Result<String>>;
+}
+
+/// A GL context together with what it renders onto
+#[cfg(any(target_arch = "wasm32", feature = "native"))]
+pub struct Surface {
+    pub context: glow::Context,
+    /// The framebuffer the final image ends up in, `None` for the default one (eg. the canvas)
+    pub framebuffer: Option<glow::Framebuffer>,
+    pub viewport: crate::render::Viewport,
+}
@@ -0,0 +1,174 @@
+//! Native rendering without a window, eg. a surfaceless EGL context (which Mesa provides with
+//! its software rasteriser, so it also works in CI) and images loaded from disk
+use std::{ffi::c_void, path::PathBuf};
+
+use anyhow::anyhow;
+use futures::future::LocalBoxFuture;
+use glow::HasContext;
+use khronos_egl as egl;
+
+use crate::{atlas::builder::Image, render::Viewport};
+
+use super::{png::read_png, ImageLoader, Surface};
+
+/// `EGL_PLATFORM_SURFACELESS_MESA`, a display without any window system
+const PLATFORM_SURFACELESS: egl::Enum = 0x31DD;
+
+/// Loads PNG images relative to a directory, eg. `public/`
+pub struct FileImageLoader {
+    root: PathBuf,
+}
+
+impl FileImageLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl ImageLoader for FileImageLoader {
+    fn load<'a>(&'a self, src: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Image>> {
+        Box::pin(async move {
+            let path = self.root.join(src);
+            read_png(&path).map_err(|e| anyhow!("failed to read image {}: {}", path.display(), e))
+        })
+    }
+}
+
+/// Loads text files relative to a directory, eg. `src/render/shaders/`
+#[cfg(feature = "dev")]
+pub struct FileTextLoader {
+    root: PathBuf,
+}
+
+#[cfg(feature = "dev")]
+impl FileTextLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[cfg(feature = "dev")]
+impl super::TextLoader for FileTextLoader {
+    fn load<'a>(&'a self, src: &'a str) -> LocalBoxFuture<'a, anyhow::Result<String>> {
+        Box::pin(async move {
+            let path = self.root.join(src);
+            std::fs::read_to_string(&path)
+                .map_err(|e| anyhow!("failed to read {}: {}", path.display(), e))
+        })
+    }
+}
+
+/// An OpenGL ES 3 context without a window. Since there is no default framebuffer, the
+/// surface renders into an offscreen framebuffer of a fixed size (which is destroyed together
+/// with the context)
+pub struct HeadlessContext {
+    egl: egl::Instance<egl::Static>,
+    display: egl::Display,
+    context: egl::Context,
+}
+
+impl HeadlessContext {
+    /// The context stays current on the calling thread as long as this is alive
+    pub fn new(width: u32, height: u32) -> anyhow::Result<(Self, Surface)> {
+        let egl = egl::Instance::new(egl::Static);
+        let display = egl
+            .get_platform_display(
+                PLATFORM_SURFACELESS,
+                std::ptr::null_mut(),
+                &[egl::ATTRIB_NONE],
+            )
+            .map_err(|e| anyhow!("failed to get a surfaceless display: {}", e))?;
+        egl.initialize(display)
+            .map_err(|e| anyhow!("failed to initialize egl: {}", e))?;
+        egl.bind_api(egl::OPENGL_ES_API)
+            .map_err(|e| anyhow!("failed to bind the opengl es api: {}", e))?;
+
+        let config = egl
+            .choose_first_config(
+                display,
+                // the surface type defaults to windows, which a surfaceless display has none of
+                &[
+                    egl::RENDERABLE_TYPE,
+                    egl::OPENGL_ES3_BIT,
+                    egl::SURFACE_TYPE,
+                    egl::PBUFFER_BIT,
+                    egl::NONE,
+                ],
+            )
+            .map_err(|e| anyhow!("failed to choose an egl config: {}", e))?
+            .ok_or_else(|| anyhow!("no egl config supports opengl es 3"))?;
+        let context = egl
+            .create_context(
+                display,
+                config,
+                None,
+                &[egl::CONTEXT_MAJOR_VERSION, 3, egl::NONE],
+            )
+            .map_err(|e| anyhow!("failed to create an egl context: {}", e))?;
+        egl.make_current(display, None, None, Some(context))
+            .map_err(|e| anyhow!("failed to make the egl context current: {}", e))?;
+
+        let gl = unsafe {
+            glow::Context::from_loader_function(|name| {
+                egl.get_proc_address(name)
+                    .map_or(std::ptr::null(), |f| f as *const c_void)
+            })
+        };
+
+        let framebuffer = unsafe {
+            let framebuffer = gl
+                .create_framebuffer()
+                .map_err(|e| anyhow!("failed to create the headless framebuffer: {}", e))?;
+            let color = gl
+                .create_renderbuffer()
+                .map_err(|e| anyhow!("failed to create the headless color buffer: {}", e))?;
+            let depth = gl
+                .create_renderbuffer()
+                .map_err(|e| anyhow!("failed to create the headless depth buffer: {}", e))?;
+
+            gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+            for (renderbuffer, format, attachment) in [
+                (color, glow::RGBA8, glow::COLOR_ATTACHMENT0),
+                (depth, glow::DEPTH_COMPONENT24, glow::DEPTH_ATTACHMENT),
+            ] {
+                gl.bind_renderbuffer(glow::RENDERBUFFER, Some(renderbuffer));
+                gl.renderbuffer_storage(glow::RENDERBUFFER, format, width as _, height as _);
+                gl.framebuffer_renderbuffer(
+                    glow::FRAMEBUFFER,
+                    attachment,
+                    glow::RENDERBUFFER,
+                    Some(renderbuffer),
+                );
+            }
+            framebuffer
+        };
+
+        let surface = Surface {
+            context: gl,
+            framebuffer: Some(framebuffer),
+            viewport: Viewport {
+                width,
+                height,
+                dpr: 1.0,
+            },
+        };
+
+        Ok((
+            Self {
+                egl,
+                display,
+                context,
+            },
+            surface,
+        ))
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        // The display is shared by all contexts of the process (eg. tests running in parallel),
+        // so it is not terminated
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_context(self.display, self.context);
+    }
+}
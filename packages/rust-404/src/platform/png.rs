@@ -0,0 +1,53 @@
+//! Reading and writing PNG files, eg. for the tools, the native platform and screenshots
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::bail;
+
+use crate::atlas::builder::Image;
+
+pub fn read_png(path: &Path) -> anyhow::Result<Image> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf)?;
+
+    let pixels = match reader.output_color_type().0 {
+        png::ColorType::RGBA => buf,
+        png::ColorType::RGB => buf
+            .chunks(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => bail!("indexed colors should have been expanded"),
+    };
+
+    Image::from_rgba(info.width, info.height, pixels)
+}
+
+pub fn write_png(path: &Path, image: &Image) -> anyhow::Result<()> {
+    encode(BufWriter::new(File::create(path)?), image)
+}
+
+/// The PNG file of `image` in memory, eg. to download it in the browser
+pub fn encode_png(image: &Image) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    encode(&mut bytes, image)?;
+    Ok(bytes)
+}
+
+fn encode(writer: impl Write, image: &Image) -> anyhow::Result<()> {
+    let mut encoder = png::Encoder::new(writer, image.width, image.height);
+    encoder.set_color(png::ColorType::RGBA);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&image.pixels)?;
+    Ok(())
+}
@@ -0,0 +1,175 @@
+//! The browser, eg. WebGL2 on a canvas and images loaded through image elements
+use anyhow::anyhow;
+use futures::future::LocalBoxFuture;
+use wasm_bindgen::{prelude::Closure, JsCast};
+use web_sys::{
+    CanvasRenderingContext2d, HtmlCanvasElement, HtmlImageElement, WebGl2RenderingContext,
+};
+
+use crate::{atlas::builder::Image, render::Viewport};
+
+use super::{ImageLoader, Surface};
+
+/// Creates a WebGL2 context for `canvas`, which is rendered onto as is until the first resize
+pub fn canvas_surface(canvas: &HtmlCanvasElement) -> anyhow::Result<Surface> {
+    let context = canvas
+        .get_context("webgl2")
+        .map_err(|e| anyhow!("failed to get webgl2 context: {:?}", e))?
+        .ok_or_else(|| anyhow!("canvas has no webgl2 context"))?
+        .dyn_into::<WebGl2RenderingContext>()
+        .map_err(|e| anyhow!("failed to get webgl2 context: {:?}", e))?;
+
+    Ok(Surface {
+        context: glow::Context::from_webgl2_context(context),
+        framebuffer: None,
+        viewport: Viewport {
+            width: canvas.width(),
+            height: canvas.height(),
+            dpr: 1.0,
+        },
+    })
+}
+
+/// Loads images relative to the page
+pub struct WebImageLoader;
+
+impl ImageLoader for WebImageLoader {
+    fn load<'a>(&'a self, src: &'a str) -> LocalBoxFuture<'a, anyhow::Result<Image>> {
+        Box::pin(async move {
+            let img = load_image_element(src).await?;
+            image_pixels(&img).map_err(|e| anyhow!("failed to read image {}: {}", src, e))
+        })
+    }
+}
+
+/// Fetches text files relative to `root`, eg. an url on the dev server
+#[cfg(feature = "dev")]
+pub struct WebTextLoader {
+    root: String,
+}
+
+#[cfg(feature = "dev")]
+impl WebTextLoader {
+    pub fn new(root: impl Into<String>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[cfg(feature = "dev")]
+impl super::TextLoader for WebTextLoader {
+    fn load<'a>(&'a self, src: &'a str) -> LocalBoxFuture<'a, anyhow::Result<String>> {
+        use wasm_bindgen_futures::JsFuture;
+        use web_sys::Response;
+
+        Box::pin(async move {
+            let url = format!("{}/{}", self.root.trim_end_matches('/'), src);
+            let window = web_sys::window().ok_or_else(|| anyhow!("no window to fetch from"))?;
+            let response: Response = JsFuture::from(window.fetch_with_str(&url))
+                .await
+                .and_then(|response| response.dyn_into())
+                .map_err(|e| anyhow!("failed to fetch {}: {:?}", url, e))?;
+            if !response.ok() {
+                anyhow::bail!("failed to fetch {}: status {}", url, response.status());
+            }
+
+            let text = response
+                .text()
+                .map_err(|e| anyhow!("failed to read {}: {:?}", url, e))?;
+            JsFuture::from(text)
+                .await
+                .map_err(|e| anyhow!("failed to read {}: {:?}", url, e))?
+                .as_string()
+                .ok_or_else(|| anyhow!("{} is not text", url))
+        })
+    }
+}
+
+/// Shows `errors` in a box on top of the page, or hides it if there are none
+#[cfg(feature = "dev")]
+pub fn show_errors(errors: &[String]) {
+    const ID: &str = "rust-404-errors";
+    const STYLE: &str = "position: fixed; top: 0; left: 0; right: 0; max-height: 50%; \
+        overflow: auto; margin: 0; padding: 8px; z-index: 1000; white-space: pre-wrap; \
+        font: 12px monospace; color: #ffb4b4; background: rgba(40, 0, 0, 0.85);";
+
+    let document = match web_sys::window().and_then(|window| window.document()) {
+        Some(document) => document,
+        None => return,
+    };
+    let overlay = match document.get_element_by_id(ID) {
+        Some(overlay) => overlay,
+        None => {
+            let overlay = match document.create_element("pre") {
+                Ok(overlay) => overlay,
+                Err(_) => return,
+            };
+            overlay.set_id(ID);
+            if let Some(body) = document.body() {
+                let _ = body.append_child(&overlay);
+            }
+            overlay
+        }
+    };
+
+    overlay.set_text_content(Some(&errors.join("\n\n")));
+    let style = if errors.is_empty() {
+        "display: none;"
+    } else {
+        STYLE
+    };
+    let _ = overlay.set_attribute("style", style);
+}
+
+async fn load_image_element(src: &str) -> anyhow::Result<HtmlImageElement> {
+    use futures::channel::oneshot::channel;
+    let (sender, receiver) = channel::<()>();
+
+    let img =
+        HtmlImageElement::new().map_err(|e| anyhow!("failed to create image element {:?}", e))?;
+
+    let closure = {
+        Closure::once(Box::new(move || {
+            sender
+                .send(())
+                .expect("failed to send image completed method");
+        }) as Box<dyn FnOnce()>)
+    };
+
+    img.set_onload(Some(closure.as_ref().unchecked_ref()));
+    img.set_src(src);
+
+    // wait for completion
+    receiver.await?;
+
+    Ok(img)
+}
+
+/// Reads back the pixels of a loaded image, by drawing it onto an offscreen canvas
+fn image_pixels(img: &HtmlImageElement) -> anyhow::Result<Image> {
+    let (width, height) = (img.natural_width(), img.natural_height());
+
+    let canvas = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| anyhow!("no document to create a canvas in"))?
+        .create_element("canvas")
+        .map_err(|e| anyhow!("failed to create canvas: {:?}", e))?
+        .dyn_into::<HtmlCanvasElement>()
+        .map_err(|e| anyhow!("failed to create canvas: {:?}", e))?;
+    canvas.set_width(width);
+    canvas.set_height(height);
+
+    let context = canvas
+        .get_context("2d")
+        .map_err(|e| anyhow!("failed to get 2d context: {:?}", e))?
+        .ok_or_else(|| anyhow!("canvas has no 2d context"))?
+        .dyn_into::<CanvasRenderingContext2d>()
+        .map_err(|e| anyhow!("failed to get 2d context: {:?}", e))?;
+    context
+        .draw_image_with_html_image_element(img, 0.0, 0.0)
+        .map_err(|e| anyhow!("failed to draw image: {:?}", e))?;
+    let data = context
+        .get_image_data(0.0, 0.0, width as f64, height as f64)
+        .map_err(|e| anyhow!("failed to read image data: {:?}", e))?;
+
+    Image::from_rgba(width, height, data.data().0)
+}
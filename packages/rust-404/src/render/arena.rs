@@ -0,0 +1,255 @@
+//! Meshes that are suballocated from a few large buffers, eg. the ones of the chunks, so that
+//! they don't need a buffer and a vertex array each and drawing them switches less state
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+use glow::{Buffer, VertexArray};
+
+use super::{Handle, Mesh, Renderer, Vertex};
+
+/// The buffers the meshes are allocated from, all clones share the same ones. Pages are kept
+/// until the arena is dropped, even once all of their meshes are gone, since allocations refer
+/// to them by index and a world that unloads chunks soon loads new ones into them
+#[derive(Clone)]
+pub struct MeshArena(Rc<Arena>);
+
+struct Arena {
+    /// Number of vertices per buffer, larger meshes get a buffer of their own size
+    page_size: i32,
+    pages: RefCell<Vec<Page>>,
+}
+
+struct Page {
+    vao: Handle<VertexArray>,
+    buffer: Handle<Buffer>,
+    free: FreeList,
+}
+
+/// Unused ranges of vertices, sorted by their start and without adjacent ones
+#[derive(Default)]
+struct FreeList(Vec<Range<i32>>);
+
+impl FreeList {
+    /// Takes `count` vertices from the start of the first range that is large enough
+    fn allocate(&mut self, count: i32) -> Option<Range<i32>> {
+        let free = &mut self.0;
+        let i = free
+            .iter()
+            .position(|range| range.len() >= count as usize)?;
+        let start = free[i].start;
+        free[i].start += count;
+        if free[i].is_empty() {
+            free.remove(i);
+        }
+        Some(start..start + count)
+    }
+
+    fn free(&mut self, range: Range<i32>) {
+        if range.is_empty() {
+            return;
+        }
+        let free = &mut self.0;
+        let i = free.partition_point(|r| r.start < range.start);
+        free.insert(i, range);
+
+        // Merge with the neighbors, if they are adjacent
+        if i + 1 < free.len() && free[i].end == free[i + 1].start {
+            free[i].end = free.remove(i + 1).end;
+        }
+        if i > 0 && free[i - 1].end == free[i].start {
+            free[i - 1].end = free.remove(i).end;
+        }
+    }
+}
+
+impl MeshArena {
+    /// The buffers are only created once they are needed
+    pub fn new(page_size: i32) -> Self {
+        Self(Rc::new(Arena {
+            page_size,
+            pages: Default::default(),
+        }))
+    }
+
+    /// Number of buffers that have been created so far
+    pub fn pages(&self) -> usize {
+        self.0.pages.borrow().len()
+    }
+
+    /// First fit in any of the existing pages
+    fn find(&self, count: i32) -> Option<Allocation> {
+        let mut pages = self.0.pages.borrow_mut();
+        pages
+            .iter_mut()
+            .enumerate()
+            .find_map(|(page, Page { free, .. })| {
+                Some(Allocation {
+                    arena: self.clone(),
+                    page,
+                    range: free.allocate(count)?,
+                })
+            })
+    }
+
+    /// Adds a page with room for `size` vertices, whose start is allocated right away
+    fn push(
+        &self,
+        vao: Handle<VertexArray>,
+        buffer: Handle<Buffer>,
+        size: i32,
+        count: i32,
+    ) -> Allocation {
+        let page = {
+            let mut pages = self.0.pages.borrow_mut();
+            pages.push(Page {
+                vao,
+                buffer,
+                free: FreeList::default(),
+            });
+            pages.len() - 1
+        };
+        self.free(page, count..size);
+        Allocation {
+            arena: self.clone(),
+            page,
+            range: 0..count,
+        }
+    }
+
+    fn free(&self, page: usize, range: Range<i32>) {
+        self.0.pages.borrow_mut()[page].free.free(range);
+    }
+}
+
+/// A range of vertices in one of the pages of an arena, which is given back when it is dropped
+pub(crate) struct Allocation {
+    arena: MeshArena,
+    page: usize,
+    range: Range<i32>,
+}
+
+impl Allocation {
+    pub fn arena(&self) -> &MeshArena {
+        &self.arena
+    }
+
+    pub fn vao(&self) -> VertexArray {
+        self.arena.0.pages.borrow()[self.page].vao.raw()
+    }
+
+    pub fn buffer(&self) -> Buffer {
+        self.arena.0.pages.borrow()[self.page].buffer.raw()
+    }
+
+    /// The first vertex
+    pub fn first(&self) -> i32 {
+        self.range.start
+    }
+
+    /// Number of vertices
+    pub fn capacity(&self) -> i32 {
+        self.range.end - self.range.start
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        self.arena.free(self.page, self.range.clone());
+    }
+}
+
+impl Renderer {
+    /// Creates a mesh in one of the buffers of `arena`, see `update_mesh` to change it
+    pub fn create_mesh_in(&self, arena: &MeshArena, vertices: &[Vertex]) -> anyhow::Result<Mesh> {
+        let allocation = self.allocate(arena, vertices.len() as i32)?;
+        let mut mesh = Mesh::in_arena(allocation);
+        self.update_mesh(&mut mesh, vertices)?;
+        Ok(mesh)
+    }
+
+    /// Room for `count` vertices, in a new page if none of the existing ones has enough
+    pub(crate) fn allocate(&self, arena: &MeshArena, count: i32) -> anyhow::Result<Allocation> {
+        if let Some(allocation) = arena.find(count) {
+            return Ok(allocation);
+        }
+
+        let size = arena.0.page_size.max(count);
+        let (vao, buffer) = unsafe { self.create_vertex_storage(size, glow::DYNAMIC_DRAW)? };
+        Ok(arena.push(vao, buffer, size, count))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A page of 100 vertices with `used` allocated, in that order
+    fn page(used: &[i32]) -> (FreeList, Vec<Range<i32>>) {
+        let mut free = FreeList::default();
+        free.free(0..100);
+        let ranges = used.iter().map(|&n| free.allocate(n).unwrap()).collect();
+        (free, ranges)
+    }
+
+    /// The free ranges as (start, end)
+    fn bounds(free: &FreeList) -> Vec<(i32, i32)> {
+        free.0.iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn allocates_first_fit() {
+        let (mut free, ranges) = page(&[10, 20, 30]);
+        assert_eq!(ranges, [0..10, 10..30, 30..60]);
+        assert_eq!(bounds(&free), [(60, 100)]);
+
+        free.free(ranges[1].clone());
+        assert_eq!(free.allocate(50), None);
+        assert_eq!(free.allocate(15), Some(10..25));
+        assert_eq!(free.allocate(5), Some(25..30));
+        assert_eq!(free.allocate(40), Some(60..100));
+        assert_eq!(bounds(&free), []);
+    }
+
+    #[test]
+    fn merges_with_one_neighbor() {
+        let (mut free, ranges) = page(&[10, 10, 10]);
+        free.free(ranges[0].clone());
+        free.free(ranges[1].clone());
+        assert_eq!(bounds(&free), [(0, 20), (30, 100)]);
+
+        let (mut free, ranges) = page(&[10, 10, 10]);
+        free.free(ranges[1].clone());
+        assert_eq!(bounds(&free), [(10, 20), (30, 100)]);
+        free.free(ranges[0].clone());
+        assert_eq!(bounds(&free), [(0, 20), (30, 100)]);
+    }
+
+    #[test]
+    fn merges_with_both_neighbors() {
+        let (mut free, ranges) = page(&[10, 10, 10, 10]);
+        free.free(ranges[0].clone());
+        free.free(ranges[2].clone());
+        assert_eq!(bounds(&free), [(0, 10), (20, 30), (40, 100)]);
+        free.free(ranges[1].clone());
+        assert_eq!(bounds(&free), [(0, 30), (40, 100)]);
+        free.free(ranges[3].clone());
+        assert_eq!(bounds(&free), [(0, 100)]);
+    }
+
+    #[test]
+    fn frees_range_at_the_end() {
+        let (mut free, ranges) = page(&[60, 40]);
+        assert_eq!(bounds(&free), []);
+        free.free(ranges[1].clone());
+        assert_eq!(bounds(&free), [(60, 100)]);
+        free.free(ranges[0].clone());
+        assert_eq!(bounds(&free), [(0, 100)]);
+        assert_eq!(free.allocate(100), Some(0..100));
+    }
+
+    #[test]
+    fn ignores_empty_ranges() {
+        let (mut free, _) = page(&[100]);
+        free.free(50..50);
+        assert_eq!(bounds(&free), []);
+    }
+}
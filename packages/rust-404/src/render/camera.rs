@@ -10,13 +10,24 @@ pub struct Camera {
     pitch: f32,
 
     recompute_dir: bool,
+    /// Width / height of the viewport
+    aspect: f32,
 
     pub(crate) projection_view: glam::Mat4,
 }
 
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Camera {
     const SPEED: f32 = 7.568;
     const MOUSE_SENSITIVITY: f32 = 0.687;
+    /// Distances of the clipping planes
+    pub const NEAR: f32 = 0.1;
+    pub const FAR: f32 = 100.0;
 
     pub fn move_dir(&self, key: &Key) -> glam::Vec3 {
         match key {
@@ -38,6 +49,7 @@ impl Camera {
             pitch: 0.0,
 
             recompute_dir: true,
+            aspect: 6.0 / 4.0,
 
             projection_view: glam::Mat4::IDENTITY,
         };
@@ -47,11 +59,17 @@ impl Camera {
     }
 
     fn calc_matrix(&mut self) {
-        let projection = glam::Mat4::perspective_rh_gl(45.0f32.to_radians(), 6.0 / 4.0, 0.1, 100.0);
+        let projection =
+            glam::Mat4::perspective_rh_gl(45.0f32.to_radians(), self.aspect, Self::NEAR, Self::FAR);
         let view = glam::Mat4::look_at_rh(self.pos, self.pos + self.dir, UP);
         self.projection_view = projection * view
     }
 
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+        self.calc_matrix();
+    }
+
     pub fn update(&mut self, dt: f32, input: &InputState) {
         let mut recompute_matrix = false;
         for pressed in input.pressed_keys() {
@@ -60,11 +78,7 @@ impl Camera {
         }
 
         if self.recompute_dir {
-            let pitch = self.pitch.to_radians();
-            let yaw = self.yaw.to_radians();
-            let xz_l = pitch.cos();
-            self.dir = glam::vec3(xz_l * yaw.cos(), pitch.sin(), xz_l * yaw.sin());
-            self.recompute_dir = false;
+            self.calc_dir();
             recompute_matrix = true;
         }
 
@@ -72,6 +86,24 @@ impl Camera {
             self.calc_matrix()
         }
     }
+
+    fn calc_dir(&mut self) {
+        let pitch = self.pitch.to_radians();
+        let yaw = self.yaw.to_radians();
+        let xz_l = pitch.cos();
+        self.dir = glam::vec3(xz_l * yaw.cos(), pitch.sin(), xz_l * yaw.sin());
+        self.recompute_dir = false;
+    }
+
+    /// Moves the camera to `pos`, looking into the direction given by `yaw` and `pitch` (in
+    /// degrees), eg. for fixed views of the world
+    pub fn set_pose(&mut self, pos: glam::Vec3, yaw: f32, pitch: f32) {
+        self.pos = pos;
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-89.9, 89.9);
+        self.calc_dir();
+        self.calc_matrix();
+    }
 }
 
 impl EventListener for Camera {
@@ -0,0 +1,92 @@
+//! Bounding boxes and view frustum tests, used to skip meshes that are not visible anyway
+use super::Vertex;
+
+/// Axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: glam::Vec3, max: glam::Vec3) -> Self {
+        Self { min, max }
+    }
+
+    /// Bounds of all vertex positions, or None if there are no vertices
+    pub fn from_vertices(vertices: &[Vertex]) -> Option<Self> {
+        let first = vertices.first()?.pos;
+        Some(
+            vertices
+                .iter()
+                .fold(Self::new(first, first), |aabb, v| Self {
+                    min: aabb.min.min(v.pos),
+                    max: aabb.max.max(v.pos),
+                }),
+        )
+    }
+
+    pub fn center(&self) -> glam::Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    /// Bounds of the box after being transformed by `transform` (which might be larger than the
+    /// box itself if there is a rotation involved)
+    pub fn transformed(&self, transform: &glam::Mat4) -> Self {
+        let corners = (0..8).map(|i| {
+            glam::vec3(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            )
+        });
+        corners.map(|c| transform.transform_point3(c)).fold(
+            Self::new(glam::Vec3::splat(f32::MAX), glam::Vec3::splat(f32::MIN)),
+            |aabb, c| Self::new(aabb.min.min(c), aabb.max.max(c)),
+        )
+    }
+}
+
+/// The six planes of a view frustum, where each plane is stored as (normal, distance) and
+/// points inside of the frustum have a positive signed distance to all of them
+pub struct Frustum {
+    planes: [glam::Vec4; 6],
+}
+
+impl Frustum {
+    /// Extract the planes from a (projection * view) matrix, after Gribb & Hartmann
+    pub fn from_matrix(projection_view: &glam::Mat4) -> Self {
+        let (r0, r1, r2, r3) = (
+            projection_view.row(0),
+            projection_view.row(1),
+            projection_view.row(2),
+            projection_view.row(3),
+        );
+
+        let normalize = |p: glam::Vec4| p / p.truncate().length();
+
+        Self {
+            planes: [
+                // left & right
+                normalize(r3 + r0),
+                normalize(r3 - r0),
+                // bottom & top
+                normalize(r3 + r1),
+                normalize(r3 - r1),
+                // near & far
+                normalize(r3 + r2),
+                normalize(r3 - r2),
+            ],
+        }
+    }
+
+    /// Conservative test, eg. might report boxes as visible that are just outside of a corner
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            // The corner of the box that is the furthest along the plane normal
+            let positive = glam::Vec3::select(normal.cmpge(glam::Vec3::ZERO), aabb.max, aabb.min);
+            normal.dot(positive) + plane.w >= 0.0
+        })
+    }
+}
@@ -0,0 +1,35 @@
+//! GL objects are owned by a `Handle`, which deletes them when it is dropped. That way they
+//! can neither leak (eg. when a mesh is replaced) nor be used after they have been deleted
+use std::rc::Rc;
+
+use glow::Context;
+
+/// Owns a GL object (eg. a `Handle<Texture>`) and keeps the context it belongs to alive
+pub struct Handle<T: Copy> {
+    context: Rc<Context>,
+    raw: T,
+    delete: unsafe fn(&Context, T),
+}
+
+impl<T: Copy> Handle<T> {
+    /// Takes the ownership of `raw`, which has to be created with `context` and is deleted
+    /// with `delete` (eg. `Context::delete_buffer`)
+    pub(crate) fn new(context: &Rc<Context>, raw: T, delete: unsafe fn(&Context, T)) -> Self {
+        Self {
+            context: Rc::clone(context),
+            raw,
+            delete,
+        }
+    }
+
+    /// The object itself, which is only valid as long as its handle is alive
+    pub fn raw(&self) -> T {
+        self.raw
+    }
+}
+
+impl<T: Copy> Drop for Handle<T> {
+    fn drop(&mut self) {
+        unsafe { (self.delete)(&self.context, self.raw) }
+    }
+}
@@ -0,0 +1,260 @@
+//! Materials decide how a mesh is shaded, eg. which shader program draws it and with which
+//! uniforms. The programs are compiled once and cached by the renderer, custom ones are created
+//! with `Renderer::create_program`
+use std::{
+    cell::RefCell,
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use glow::{Context, HasContext, Program, UniformLocation};
+
+use super::Handle;
+
+/// The vertex shader of the built in materials, which custom ones can reuse. Besides the
+/// inputs of `solid.frag`, it passes a flat `pass_tint`, eg. the color of an `Instance`
+pub const STANDARD_VERTEX_SHADER: &str = include_str!("shaders/solid.vert");
+/// The lit fragment shader of the built in materials, which samples the block textures or
+/// uses the `solid_color` uniform (with `SOLID_COLOR` defined)
+pub(crate) const STANDARD_FRAGMENT_SHADER: &str = include_str!("shaders/solid.frag");
+
+/// A shader program in the renderer's cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProgramId(usize);
+
+impl ProgramId {
+    /// The built in programs, which are always the first ones in the cache
+    pub(crate) const ATLAS: Self = Self(0);
+    pub(crate) const SOLID: Self = Self(1);
+}
+
+/// Value of a material uniform
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Uniform {
+    Int(i32),
+    Float(f32),
+    Vec2(glam::Vec2),
+    Vec3(glam::Vec3),
+    Vec4(glam::Vec4),
+    Mat4(glam::Mat4),
+}
+
+impl Uniform {
+    /// # Safety
+    /// The program `loc` belongs to has to be in use
+    unsafe fn apply(&self, context: &Context, loc: Option<&UniformLocation>) {
+        match self {
+            Self::Int(value) => context.uniform_1_i32(loc, *value),
+            Self::Float(value) => context.uniform_1_f32(loc, *value),
+            Self::Vec2(value) => context.uniform_2_f32_slice(loc, value.as_ref()),
+            Self::Vec3(value) => context.uniform_3_f32_slice(loc, value.as_ref()),
+            Self::Vec4(value) => context.uniform_4_f32_slice(loc, value.as_ref()),
+            Self::Mat4(value) => context.uniform_matrix_4_f32_slice(loc, false, value.as_ref()),
+        }
+    }
+
+    /// Hashes the bits of the value, since floats aren't `Hash`
+    fn hash(&self, state: &mut impl Hasher) {
+        let floats: &[f32] = match self {
+            Self::Int(value) => {
+                value.hash(state);
+                return;
+            }
+            Self::Float(value) => std::slice::from_ref(value),
+            Self::Vec2(value) => value.as_ref(),
+            Self::Vec3(value) => value.as_ref(),
+            Self::Vec4(value) => value.as_ref(),
+            Self::Mat4(value) => value.as_ref(),
+        };
+        std::mem::discriminant(self).hash(state);
+        floats.iter().for_each(|f| f.to_bits().hash(state));
+    }
+}
+
+impl From<i32> for Uniform {
+    fn from(value: i32) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<f32> for Uniform {
+    fn from(value: f32) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<glam::Vec2> for Uniform {
+    fn from(value: glam::Vec2) -> Self {
+        Self::Vec2(value)
+    }
+}
+
+impl From<glam::Vec3> for Uniform {
+    fn from(value: glam::Vec3) -> Self {
+        Self::Vec3(value)
+    }
+}
+
+impl From<glam::Vec4> for Uniform {
+    fn from(value: glam::Vec4) -> Self {
+        Self::Vec4(value)
+    }
+}
+
+impl From<glam::Mat4> for Uniform {
+    fn from(value: glam::Mat4) -> Self {
+        Self::Mat4(value)
+    }
+}
+
+/// A shader program together with its uniforms. Besides these, the renderer sets the same
+/// uniforms as for `solid.frag` (eg. `view_projection`, `light_dir` or `time`), which the
+/// program may use. Its fragment shader has to write the color to location 0 and the normal
+/// (as `0.5 * normal + 0.5`) to location 1, like `solid.frag` does. The lighting of the built in
+/// materials can be reused with `#include "lighting.glsl"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    program: ProgramId,
+    uniforms: Vec<(&'static str, Uniform)>,
+    /// Hash of the program and the uniforms, see `key`
+    key: u64,
+}
+
+impl Material {
+    pub fn new(program: ProgramId) -> Self {
+        let mut material = Self {
+            program,
+            uniforms: Vec::new(),
+            key: 0,
+        };
+        material.update_key();
+        material
+    }
+
+    /// The block textures, eg. the atlas or the texture array (depending on the backend)
+    pub fn atlas() -> Self {
+        Self::new(ProgramId::ATLAS)
+    }
+
+    /// A single lit color
+    pub fn solid(color: glam::Vec4) -> Self {
+        Self::new(ProgramId::SOLID).with("solid_color", color)
+    }
+
+    /// Sets the uniform `name` to `value`, whenever the material is used
+    pub fn with(mut self, name: &'static str, value: impl Into<Uniform>) -> Self {
+        let value = value.into();
+        match self.uniforms.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.uniforms.push((name, value)),
+        }
+        self.update_key();
+        self
+    }
+
+    pub fn program(&self) -> ProgramId {
+        self.program
+    }
+
+    /// Equal materials have the same key, so that draw calls can be grouped by their material
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    fn update_key(&mut self) {
+        let mut hasher = DefaultHasher::new();
+        self.program.hash(&mut hasher);
+        for (name, value) in &self.uniforms {
+            name.hash(&mut hasher);
+            value.hash(&mut hasher);
+        }
+        self.key = hasher.finish();
+    }
+
+    /// # Safety
+    /// The material's program has to be in use
+    pub(crate) unsafe fn apply(&self, context: &Context, programs: &ProgramCache) {
+        for (name, value) in &self.uniforms {
+            let loc = programs.location(context, self.program, name);
+            value.apply(context, loc.as_ref());
+        }
+    }
+}
+
+/// Linked programs by their (vertex, fragment) source, so that every one is only compiled once
+#[derive(Default)]
+pub(crate) struct ProgramCache {
+    programs: Vec<CachedProgram>,
+    ids: HashMap<(String, String), ProgramId>,
+}
+
+struct CachedProgram {
+    program: Handle<Program>,
+    /// Locations of the material uniforms, which are looked up when they are first set
+    locations: RefCell<HashMap<&'static str, Option<UniformLocation>>>,
+}
+
+impl CachedProgram {
+    fn new(program: Handle<Program>) -> Self {
+        Self {
+            program,
+            locations: Default::default(),
+        }
+    }
+}
+
+impl ProgramCache {
+    pub fn get(&self, id: ProgramId) -> Program {
+        self.programs[id.0].program.raw()
+    }
+
+    /// Location of the uniform `name` of the program `id`, `None` if it has no such uniform
+    pub fn location(
+        &self,
+        context: &Context,
+        id: ProgramId,
+        name: &'static str,
+    ) -> Option<UniformLocation> {
+        let CachedProgram { program, locations } = &self.programs[id.0];
+        locations
+            .borrow_mut()
+            .entry(name)
+            .or_insert_with(|| unsafe { context.get_uniform_location(program.raw(), name) })
+            .as_ref()
+            .cloned()
+    }
+
+    pub fn find(&self, vertex: &str, fragment: &str) -> Option<ProgramId> {
+        self.ids
+            .get(&(vertex.to_string(), fragment.to_string()))
+            .copied()
+    }
+
+    pub fn insert(&mut self, vertex: &str, fragment: &str, program: Handle<Program>) -> ProgramId {
+        let id = ProgramId(self.programs.len());
+        self.programs.push(CachedProgram::new(program));
+        self.ids
+            .insert((vertex.to_string(), fragment.to_string()), id);
+        id
+    }
+
+    pub fn programs(&self) -> impl Iterator<Item = Program> + '_ {
+        self.programs.iter().map(|cached| cached.program.raw())
+    }
+
+    /// The (vertex, fragment) source every program has been created from
+    #[cfg(feature = "dev")]
+    pub fn sources(&self) -> Vec<(ProgramId, String, String)> {
+        self.ids
+            .iter()
+            .map(|((vertex, fragment), id)| (*id, vertex.clone(), fragment.clone()))
+            .collect()
+    }
+
+    /// Swaps the program behind `id`, eg. after its shaders have been reloaded, which deletes
+    /// the previous one (and forgets its uniform locations)
+    #[cfg(feature = "dev")]
+    pub fn replace(&mut self, id: ProgramId, program: Handle<Program>) {
+        self.programs[id.0] = CachedProgram::new(program);
+    }
+}
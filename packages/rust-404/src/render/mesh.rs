@@ -1,27 +1,73 @@
 use glow::{Buffer, VertexArray};
+use std::cmp::Ordering;
 
-use crate::atlas::BlockTexture;
+use crate::atlas::{BlockTexture, TextureBackend, TextureLayout};
+
+use super::frustum::Aabb;
 
 use super::camera::UP;
-use super::Vertex;
+use super::{arena::Allocation, Handle, Vertex};
 
+/// The GL objects are deleted (or the vertices given back to their arena), when the mesh is
+/// dropped
 pub struct Mesh {
-    pub vao: VertexArray,
-    pub buffer: Buffer,
+    pub(crate) storage: MeshStorage,
     pub vertices_count: i32,
 }
 
+/// Where the vertices of a mesh are
+pub(crate) enum MeshStorage {
+    /// A buffer of its own, which has room for `capacity` vertices
+    Buffer {
+        vao: Handle<VertexArray>,
+        buffer: Handle<Buffer>,
+        capacity: i32,
+    },
+    Arena(Allocation),
+}
+
 impl Mesh {
-    pub fn new(vao: VertexArray, buffer: Buffer, vertices_count: i32) -> Self {
+    pub(crate) fn new(
+        vao: Handle<VertexArray>,
+        buffer: Handle<Buffer>,
+        vertices_count: i32,
+    ) -> Self {
         Self {
-            vao,
-            buffer,
+            storage: MeshStorage::Buffer {
+                vao,
+                buffer,
+                capacity: vertices_count,
+            },
             vertices_count,
         }
     }
+
+    /// An empty mesh in `allocation`
+    pub(crate) fn in_arena(allocation: Allocation) -> Self {
+        Self {
+            storage: MeshStorage::Arena(allocation),
+            vertices_count: 0,
+        }
+    }
+
+    pub(crate) fn vao(&self) -> VertexArray {
+        match &self.storage {
+            MeshStorage::Buffer { vao, .. } => vao.raw(),
+            MeshStorage::Arena(allocation) => allocation.vao(),
+        }
+    }
+
+    /// Index of the first vertex in the buffer
+    pub(crate) fn first(&self) -> i32 {
+        match &self.storage {
+            MeshStorage::Buffer { .. } => 0,
+            MeshStorage::Arena(allocation) => allocation.first(),
+        }
+    }
 }
 
-pub fn cube(scale: glam::Vec3) -> Vec<Vertex> {
+/// A stone textured cube, with the texture coordinates of `layout`
+pub fn cube(scale: glam::Vec3, layout: &TextureLayout) -> Vec<Vertex> {
     let scale = 0.5 * scale;
 
     let mut vertices = Vec::with_capacity(36);
@@ -40,7 +86,7 @@ pub fn cube(scale: glam::Vec3) -> Vec<Vertex> {
             Vertex {
                 pos: base + (a * right + b * orthogonal),
                 normal: norm,
-                tex_coord: TEXTURE.tex_coord(local_coord),
+                tex_coord: TEXTURE.tex_coord(layout, local_coord),
                 base_loc: glam::Vec3::splat(0.0),
             }
         };
@@ -74,6 +120,8 @@ pub fn build_selection_ring() -> Vec<Vertex> {
     const SCALE: f32 = 0.5 - (WIDTH / 2.0);
     let dirs = [glam::Vec3::X, glam::Vec3::Z];
     let signs = [-1f32, 1f32];
+    // The ring is drawn in a solid color, so its texture coordinates don't matter
+    let layout = TextureLayout::new(TextureBackend::Atlas);
     let mut result = Vec::new();
     for (dir, sign, add_length) in dirs
         .iter()
@@ -88,7 +136,7 @@ pub fn build_selection_ring() -> Vec<Vertex> {
             side * (1.0 - 2.0 * WIDTH)
         };
         let extend = side + WIDTH * dir + WIDTH * UP;
-        result.extend(cube(extend).into_iter().map(|v| Vertex {
+        result.extend(cube(extend, &layout).into_iter().map(|v| Vertex {
             pos: v.pos + base + (WIDTH * 0.5 * UP),
             ..v
         }));
@@ -97,7 +145,25 @@ pub fn build_selection_ring() -> Vec<Vertex> {
     result
 }
 
-#[derive(Debug, Clone)]
+/// Places the selection ring onto `face` of the block at `loc`, where `bounds` is the box of
+/// the block shape that face belongs to (relative to the block center)
+pub fn selection_ring_transform(loc: glam::Vec3, face: &Face, bounds: &Aabb) -> glam::Mat4 {
+    let normal = face.normal();
+    let half = 0.5 * (bounds.max - bounds.min);
+    let center = loc + bounds.center() + normal * half.dot(normal.abs());
+
+    // The ring is modelled around the up axis, so it is scaled to the face in that space
+    let rotation = glam::Quat::from_rotation_arc(UP, normal);
+    let size = 2.0 * half;
+    let scale = glam::vec3(
+        size.dot((rotation * glam::Vec3::X).abs()),
+        1.0,
+        size.dot((rotation * glam::Vec3::Z).abs()),
+    );
+    glam::Mat4::from_scale_rotation_translation(scale, rotation, center)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Face {
     NegativeX,
     PositiveX,
@@ -117,6 +183,29 @@ impl Face {
         Self::PositiveZ,
     ];
 
+    /// Position of the face in `Face::FACES`
+    pub fn index(&self) -> usize {
+        match *self {
+            Face::NegativeX => 0,
+            Face::PositiveX => 1,
+            Face::NegativeY => 2,
+            Face::PositiveY => 3,
+            Face::NegativeZ => 4,
+            Face::PositiveZ => 5,
+        }
+    }
+
+    pub fn opposite(&self) -> Self {
+        match *self {
+            Face::NegativeX => Face::PositiveX,
+            Face::PositiveX => Face::NegativeX,
+            Face::NegativeY => Face::PositiveY,
+            Face::PositiveY => Face::NegativeY,
+            Face::NegativeZ => Face::PositiveZ,
+            Face::PositiveZ => Face::NegativeZ,
+        }
+    }
+
     pub fn normal(&self) -> glam::Vec3 {
         self.neighbor_dir().as_vec3()
     }
@@ -147,12 +236,13 @@ impl Face {
 fn calc_face(
     norm: glam::Vec3,
     orthogonal: glam::Vec3,
-    pos: &glam::IVec3,
+    center: glam::Vec3,
+    scale: f32,
+    base_loc: glam::Vec3,
     vertices: &mut Vec<Vertex>,
-    t: BlockTexture,
+    tex_coord: impl Fn(glam::Vec2) -> glam::Vec3,
 ) {
-    let scale = 0.5f32;
-    let base = pos.as_vec3() + norm * scale;
+    let base = center + norm * scale;
     let orthogonal = orthogonal * scale;
 
     let right = (norm.cross(orthogonal)).normalize() * scale;
@@ -164,8 +254,8 @@ fn calc_face(
         Vertex {
             pos: base + (a * right + b * orthogonal),
             normal: norm,
-            tex_coord: t.tex_coord(local_coord),
-            base_loc: pos.as_vec3(),
+            tex_coord: tex_coord(local_coord),
+            base_loc,
         }
     };
 
@@ -180,6 +270,173 @@ fn calc_face(
     vertices.push(vec(1.0, 1.0));
 }
 
-pub fn build_face(vec: &mut Vec<Vertex>, face: &Face, pos: &glam::IVec3, t: BlockTexture) {
-    calc_face(face.normal(), face.orthogonal(), pos, vec, t)
+pub fn build_face(
+    vec: &mut Vec<Vertex>,
+    face: &Face,
+    pos: &glam::IVec3,
+    t: BlockTexture,
+    layout: &TextureLayout,
+) {
+    let pos = pos.as_vec3();
+    let tex_coord = |local_coord| t.tex_coord(layout, local_coord);
+    calc_face(
+        face.normal(),
+        face.orthogonal(),
+        pos,
+        0.5,
+        pos,
+        vec,
+        tex_coord,
+    )
+}
+
+/// Two diagonal quads crossing in the center of the block, eg. for plants. They are lit as if
+/// they were facing up, since they are seen from both sides
+pub fn build_cross(
+    vec: &mut Vec<Vertex>,
+    pos: &glam::IVec3,
+    t: BlockTexture,
+    layout: &TextureLayout,
+) {
+    let center = pos.as_vec3();
+    let up = 0.5 * UP;
+
+    for diagonal in [glam::vec3(0.5, 0.0, 0.5), glam::vec3(0.5, 0.0, -0.5)].iter() {
+        let vertex = |a: f32, b: f32| -> Vertex {
+            let local_coord = glam::vec2(a, -b) * 0.5 + glam::vec2(0.5, 0.5);
+            Vertex {
+                pos: center + a * *diagonal + b * up,
+                normal: UP,
+                tex_coord: t.tex_coord(layout, local_coord),
+                base_loc: center,
+            }
+        };
+
+        // First triangle
+        vec.push(vertex(-1.0, 1.0));
+        vec.push(vertex(-1.0, -1.0));
+        vec.push(vertex(1.0, -1.0));
+
+        // second triangle
+        vec.push(vertex(-1.0, 1.0));
+        vec.push(vertex(1.0, -1.0));
+        vec.push(vertex(1.0, 1.0));
+    }
+}
+
+/// Face of a box (relative to the block center) inside of the block at `pos`. The texture is
+/// projected onto the box, so that eg. a slab shows the lower half of the texture on its sides.
+/// The face is rotated around the block center by `rotation` afterwards, along with its texture
+pub fn build_box_face(
+    vec: &mut Vec<Vertex>,
+    face: &Face,
+    pos: &glam::IVec3,
+    bounds: &Aabb,
+    rotation: &glam::Mat3,
+    t: BlockTexture,
+    layout: &TextureLayout,
+) {
+    let norm = face.normal();
+    let orthogonal = face.orthogonal();
+    let right = norm.cross(orthogonal).normalize();
+
+    let half = 0.5 * (bounds.max - bounds.min);
+    let base = bounds.center() + norm * half.dot(norm.abs());
+    let (right_extend, orthogonal_extend) = (half.dot(right.abs()), half.dot(orthogonal.abs()));
+
+    let vertex = |a: f32, b: f32| -> Vertex {
+        let local = base + a * right_extend * right + b * orthogonal_extend * orthogonal;
+        let local_coord = glam::vec2(local.dot(right), -local.dot(orthogonal)) + 0.5;
+        Vertex {
+            pos: pos.as_vec3() + *rotation * local,
+            normal: *rotation * norm,
+            tex_coord: t.tex_coord(layout, local_coord),
+            base_loc: pos.as_vec3(),
+        }
+    };
+
+    // First triangle
+    vec.push(vertex(-1.0, 1.0));
+    vec.push(vertex(-1.0, -1.0));
+    vec.push(vertex(1.0, -1.0));
+
+    // second triangle
+    vec.push(vertex(-1.0, 1.0));
+    vec.push(vertex(1.0, -1.0));
+    vec.push(vertex(1.0, 1.0));
+}
+
+/// Face of a cube of `size` blocks (starting at the block `base_loc`), for level of detail meshes
+pub fn build_lod_face(
+    vec: &mut Vec<Vertex>,
+    face: &Face,
+    base_loc: &glam::IVec3,
+    size: i32,
+    t: BlockTexture,
+    layout: &TextureLayout,
+) {
+    // Block centers are at integer coordinates, so the cube starts half a block before base_loc
+    let center = base_loc.as_vec3() + 0.5 * (size - 1) as f32;
+    calc_face(
+        face.normal(),
+        face.orthogonal(),
+        center,
+        0.5 * size as f32,
+        base_loc.as_vec3(),
+        vec,
+        |local_coord| t.tex_coord(layout, local_coord),
+    )
+}
+
+/// Number of vertices every face (two triangles) of the mesh builders consists of
+pub const FACE_VERTICES: usize = 6;
+
+/// Orders the faces of `vertices` back to front as seen from `eye` (in the same space), so that
+/// translucent faces are blended in the right order
+pub fn sort_faces(vertices: &mut [Vertex], eye: glam::Vec3) {
+    let distance = |face: &[Vertex]| {
+        let center = face.iter().fold(glam::Vec3::ZERO, |sum, v| sum + v.pos) / face.len() as f32;
+        center.distance_squared(eye)
+    };
+    let mut faces: Vec<_> = vertices
+        .chunks_exact(FACE_VERTICES)
+        .map(|face| (distance(face), face.to_vec()))
+        .collect();
+    faces.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+
+    for (target, (_, face)) in vertices.chunks_exact_mut(FACE_VERTICES).zip(faces) {
+        target.copy_from_slice(&face);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorts_faces_back_to_front() {
+        let layout = TextureLayout::new(TextureBackend::Atlas);
+        let mut vertices = Vec::new();
+        for x in [1, 3, 2] {
+            let pos = glam::ivec3(x, 0, 0);
+            build_face(
+                &mut vertices,
+                &Face::PositiveY,
+                &pos,
+                BlockTexture::Water,
+                &layout,
+            );
+        }
+
+        let order = |vertices: &[Vertex]| -> Vec<f32> {
+            vertices
+                .chunks_exact(FACE_VERTICES)
+                .map(|face| face[0].base_loc.x)
+                .collect()
+        };
+        sort_faces(&mut vertices, glam::Vec3::ZERO);
+        assert_eq!(order(&vertices), [3.0, 2.0, 1.0]);
+        sort_faces(&mut vertices, glam::vec3(4.0, 0.0, 0.0));
+        assert_eq!(order(&vertices), [1.0, 2.0, 3.0]);
+    }
 }
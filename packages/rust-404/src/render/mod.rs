@@ -1,175 +1,430 @@
 use __core::cmp::Ordering;
 use anyhow::{anyhow, bail};
 use bytemuck::*;
+use enum_iterator::IntoEnumIterator;
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
 
-use glow::{Context, Framebuffer, HasContext, PixelPackData, Program, Shader, Texture};
-use wasm_bindgen::{prelude::Closure, JsCast};
-use web_sys::HtmlImageElement;
+use glow::{
+    Buffer, Context, Framebuffer, HasContext, PixelPackData, Program, Renderbuffer, Shader,
+    Texture, VertexArray,
+};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{
+    atlas::{
+        builder::{AtlasBuilder, AtlasLayout, Image, TileRect},
+        BlockTexture, TextureBackend, TextureLayout,
+    },
+    platform::{ImageLoader, Surface},
+    world::{chunk::CHUNK_SIZE, RENDER_DISTANCE},
+};
 
-use crate::{atlas::BlockTexture, world::chunk::CHUNK_SIZE};
+#[cfg(feature = "dev")]
+use crate::platform::TextLoader;
 
 use self::{
     camera::Camera,
-    ui::{UiFrame, UiRenderer},
+    frustum::{Aabb, Frustum},
+    material::{ProgramCache, STANDARD_FRAGMENT_SHADER, STANDARD_VERTEX_SHADER},
+    post::{PostEffect, PostProcessor},
+    preprocessor::{with_define, Preprocessor},
+    sky::Sky,
 };
+pub use arena::MeshArena;
+pub use handle::Handle;
+pub use material::{Material, ProgramId, Uniform};
 pub use mesh::*;
 pub use ui::*;
 
+pub mod arena;
 pub mod camera;
+pub mod frustum;
+pub mod handle;
+pub mod material;
 pub mod mesh;
+pub mod post;
+mod preprocessor;
+pub mod resource_pack;
+pub mod sky;
 pub mod ui;
 
-const VERTEX_CODE: &'static str = include_str!("shaders/solid.vert");
-const FRAGMENT_CODE: &'static str = include_str!("shaders/solid.frag");
+// Defined in the shaders as well, see `Preprocessor`
+const MAX_ANIMATIONS: usize = 4;
+const MAX_FRAMES: usize = 16;
 
 const PICKING_VERTEX_CODE: &'static str = include_str!("shaders/picking.vert");
 const PICKING_FRAGMENT_CODE: &'static str = include_str!("shaders/picking.frag");
 
+const SKY_VERTEX_CODE: &str = include_str!("shaders/sky.vert");
+const SKY_FRAGMENT_CODE: &str = include_str!("shaders/sky.frag");
+
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 #[repr(C)]
 pub struct Vertex {
     pos: glam::Vec3,
     normal: glam::Vec3,
-    tex_coord: glam::Vec2,
+    /// (u, v, layer), see `TextureBackend`
+    tex_coord: glam::Vec3,
     base_loc: glam::Vec3,
 }
 
+/// A copy of a mesh that is drawn together with all other copies of it, see
+/// `RenderTask::push_instance`
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+#[repr(C)]
+pub struct Instance {
+    pub transform: glam::Mat4,
+    /// Multiplied with the color of the material, eg. white keeps it as is
+    pub color: glam::Vec4,
+}
+
+/// Size of the drawing buffer (in physical pixels) and how many of them make up a CSS pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+    pub dpr: f32,
+}
+
+impl Viewport {
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+
+    /// Size in CSS pixels, which is what the UI is laid out in
+    pub fn logical_size(&self) -> glam::Vec2 {
+        glam::vec2(self.width as f32, self.height as f32) / self.dpr
+    }
+}
+
+/// Shader sources by the name of their file in `shaders/` (eg. `solid.frag`)
+#[cfg(feature = "dev")]
+pub type ShaderSources = std::collections::HashMap<String, String>;
+
 const FLOAT_SIZE: i32 = std::mem::size_of::<f32>() as i32;
+const VERTEX_SIZE: i32 = std::mem::size_of::<Vertex>() as i32;
+
+/// Geometry is drawn in separate passes, depending on how it handles transparency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RenderLayer {
+    Opaque,
+    /// Fully opaque or fully transparent texels (eg. leaves), which are alpha tested
+    Cutout,
+    /// Blended geometry (eg. water or glass), that is drawn back to front after everything else
+    Translucent,
+}
+
+impl RenderLayer {
+    pub const LAYERS: [Self; 3] = [Self::Opaque, Self::Cutout, Self::Translucent];
+
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
 
-pub enum Material {
-    Atlas,
-    Solid(glam::Vec4),
+struct DrawCall<'a> {
+    mesh: &'a Mesh,
+    transform: Option<glam::Mat4>,
+    material: Material,
+    layer: RenderLayer,
+    /// World space center of the mesh, used to sort translucent geometry
+    center: glam::Vec3,
+    /// Draws the mesh once per instance instead (with their transforms and colors)
+    instances: Option<Vec<Instance>>,
+    /// Whether the picking pass draws the mesh, which requires its `base_loc` to be the
+    /// location of the block each vertex belongs to
+    pickable: bool,
+}
+
+/// Statistics of the last rendered frame, for a debug overlay
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenderStats {
+    pub meshes_submitted: u32,
+    pub meshes_culled: u32,
+    pub meshes_occluded: u32,
+    pub triangles: u32,
 }
 
 pub struct RenderTask<'a> {
-    meshes: Vec<(&'a Mesh, Option<glam::Mat4>, Material)>,
+    meshes: Vec<DrawCall<'a>>,
+    /// Indices of the instanced draw calls in `meshes`
+    batches: Vec<usize>,
+    frustum: Frustum,
+    culled: u32,
+    occluded: u32,
 }
 
 impl<'a> RenderTask<'a> {
-    pub fn push(&mut self, mesh: &'a Mesh) {
-        self.meshes.push((mesh, None, Material::Atlas));
+    /// Pushes the mesh only if its bounds (in model space) are inside of the view frustum.
+    /// Meshes that aren't `pickable` (eg. lower levels of detail) are skipped by `pick`
+    pub fn push_culled(
+        &mut self,
+        mesh: &'a Mesh,
+        transform: glam::Mat4,
+        bounds: &Aabb,
+        layer: RenderLayer,
+        pickable: bool,
+    ) {
+        let bounds = bounds.transformed(&transform);
+        if self.frustum.intersects(&bounds) {
+            self.meshes.push(DrawCall {
+                mesh,
+                transform: Some(transform),
+                material: Material::atlas(),
+                layer,
+                center: bounds.center(),
+                instances: None,
+                pickable,
+            });
+        } else {
+            self.culled += 1;
+        }
+    }
+
+    /// Pushes a copy of `mesh` (eg. a dropped item or a particle). All copies of the same mesh
+    /// with the same material in the same layer are batched into a single draw call. They are
+    /// neither culled nor pickable and translucent batches are sorted by the center of all of
+    /// their instances
+    pub fn push_instance(
+        &mut self,
+        mesh: &'a Mesh,
+        instance: Instance,
+        material: &Material,
+        layer: RenderLayer,
+    ) {
+        let pos = instance.transform.transform_point3(glam::Vec3::ZERO);
+        let meshes = &mut self.meshes;
+        let batch = self.batches.iter().copied().find(|&i| {
+            let call = &meshes[i];
+            call.layer == layer && std::ptr::eq(call.mesh, mesh) && call.material == *material
+        });
+
+        match batch {
+            Some(i) => {
+                let call = &mut meshes[i];
+                let instances = call.instances.get_or_insert_with(Vec::new);
+                instances.push(instance);
+                call.center += (pos - call.center) / instances.len() as f32;
+            }
+            None => {
+                self.batches.push(meshes.len());
+                meshes.push(DrawCall {
+                    mesh,
+                    transform: None,
+                    material: material.clone(),
+                    layer,
+                    center: pos,
+                    instances: Some(vec![instance]),
+                    pickable: false,
+                });
+            }
+        }
+    }
+
+    /// Records a mesh that has been skipped, since it is hidden behind other geometry
+    pub fn mark_occluded(&mut self) {
+        self.occluded += 1;
     }
+
     pub fn push_with_transform_and_material(
         &mut self,
         mesh: &'a Mesh,
         transform: glam::Mat4,
         material: Material,
     ) {
-        self.meshes.push((mesh, Some(transform), material))
+        self.meshes.push(DrawCall {
+            mesh,
+            transform: Some(transform),
+            material,
+            layer: RenderLayer::Opaque,
+            center: transform.transform_point3(glam::Vec3::ZERO),
+            instances: None,
+            pickable: true,
+        })
     }
 }
 
 pub struct Renderer {
-    context: Context,
+    context: Rc<Context>,
+    /// Where the final image ends up, `None` for the default framebuffer
+    output: Cell<Option<Framebuffer>>,
+    images: Box<dyn ImageLoader>,
     ui_renderer: UiRenderer,
-    program: Program,
-    picking_program: Program,
-    picking_fb: Framebuffer,
-    atlas: Texture,
+    post: PostProcessor,
+    /// The programs of all materials, see `ProgramId`
+    programs: RefCell<ProgramCache>,
+    /// Per instance attributes of the instanced draw calls, refilled for each of them
+    instance_buffer: Handle<Buffer>,
+    picking_program: RefCell<Handle<Program>>,
+    picking_fb: Handle<Framebuffer>,
+    picking_texture: Handle<Texture>,
+    /// Index (+ 1) of the draw call that has been hit, 0 if there is none
+    picking_ids: Handle<Texture>,
+    picking_depth: Handle<Renderbuffer>,
+    viewport: Cell<Viewport>,
+    sky_program: RefCell<Handle<Program>>,
+    // The sky is a fullscreen triangle, that is generated in the vertex shader,
+    // but we still need a vertex array to draw it
+    sky_vertex_array: Handle<VertexArray>,
+    atlas: RefCell<Handle<Texture>>,
+    /// Only used with `TextureBackend::Array`, the atlas is still needed for the UI
+    texture_array: RefCell<Option<Handle<Texture>>>,
+    /// The texture coordinates the meshes of this renderer are built with
+    texture_layout: RefCell<Rc<TextureLayout>>,
+    stats: Cell<RenderStats>,
+    /// Errors of the last `reload_shaders`
+    #[cfg(feature = "dev")]
+    shader_errors: RefCell<Vec<String>>,
 }
 
 impl Renderer {
-    pub async fn new(context: Context) -> anyhow::Result<Self> {
-        let program = unsafe {
-            let vert_shader = Self::compile_shader(&context, glow::VERTEX_SHADER, VERTEX_CODE)?;
-            let frag_shader = Self::compile_shader(&context, glow::FRAGMENT_SHADER, FRAGMENT_CODE)?;
-            Self::link_program(&context, vert_shader, frag_shader)?
-        };
+    /// Border (in pixels) around each tile of atlases that are packed at runtime
+    const ATLAS_EXTRUSION: u32 = 8;
+
+    /// `backend` decides how the block textures are sampled, see `texture_layout`
+    pub async fn new(
+        surface: Surface,
+        backend: TextureBackend,
+        images: Box<dyn ImageLoader>,
+    ) -> anyhow::Result<Self> {
+        let Surface {
+            context,
+            framebuffer: output,
+            viewport,
+        } = surface;
+        let context = Rc::new(context);
+        let texture_layout = TextureLayout::new(backend);
+        let preprocessor = Preprocessor::new(backend);
+
+        // The built in programs, in the order of their `ProgramId`s
+        let mut programs = ProgramCache::default();
+        for fragment in [
+            STANDARD_FRAGMENT_SHADER.to_string(),
+            with_define(STANDARD_FRAGMENT_SHADER, "SOLID_COLOR"),
+        ] {
+            let program = unsafe {
+                Self::material_program(
+                    &context,
+                    &preprocessor,
+                    &texture_layout,
+                    STANDARD_VERTEX_SHADER,
+                    &fragment,
+                )?
+            };
+            programs.insert(STANDARD_VERTEX_SHADER, &fragment, program);
+        }
 
         let picking_program = unsafe {
-            let picking_vert =
-                Self::compile_shader(&context, glow::VERTEX_SHADER, PICKING_VERTEX_CODE)?;
-            let picking_frag =
-                Self::compile_shader(&context, glow::FRAGMENT_SHADER, PICKING_FRAGMENT_CODE)?;
-            Self::link_program(&context, picking_vert, picking_frag)?
+            Self::compile_program(
+                &context,
+                &preprocessor,
+                PICKING_VERTEX_CODE,
+                PICKING_FRAGMENT_CODE,
+            )?
+        };
+
+        let sky_program = unsafe {
+            Self::compile_program(&context, &preprocessor, SKY_VERTEX_CODE, SKY_FRAGMENT_CODE)?
+        };
+
+        let instance_buffer = unsafe {
+            let buffer = context
+                .create_buffer()
+                .map_err(|e| anyhow!("failed to create instance buffer: {}", e))?;
+            Handle::new(&context, buffer, Context::delete_buffer)
+        };
+
+        let sky_vertex_array = unsafe {
+            let vertex_array = context
+                .create_vertex_array()
+                .map_err(|e| anyhow!("failed to create sky vertex array: {}", e))?;
+            Handle::new(&context, vertex_array, Context::delete_vertex_array)
         };
 
-        // create picking framebuffer
-        let picking_fb = unsafe {
+        // create picking framebuffer, its storage is allocated by `resize`
+        let (picking_fb, picking_texture, picking_ids, picking_depth) = unsafe {
             let texture = context
                 .create_texture()
                 .map_err(|e| anyhow!("failed to create picking color attachment: {}", e))?;
+            let texture = Handle::new(&context, texture, Context::delete_texture);
 
-            context.bind_texture(glow::TEXTURE_2D, Some(texture));
-
-            context.tex_image_2d(
-                glow::TEXTURE_2D,
-                0,
-                glow::RGBA as i32,
-                600,
-                400,
-                0,
-                glow::RGBA,
-                glow::UNSIGNED_BYTE,
-                None,
-            );
+            let ids = context
+                .create_texture()
+                .map_err(|e| anyhow!("failed to create picking id attachment: {}", e))?;
+            let ids = Handle::new(&context, ids, Context::delete_texture);
 
             let renderbuffer = context
                 .create_renderbuffer()
                 .map_err(|e| anyhow!("failed to create picking depth renderbuffer: {}", e))?;
-
-            context.bind_renderbuffer(glow::RENDERBUFFER, Some(renderbuffer));
-
-            context.renderbuffer_storage(glow::RENDERBUFFER, glow::DEPTH_COMPONENT16, 600, 400);
+            let renderbuffer = Handle::new(&context, renderbuffer, Context::delete_renderbuffer);
+
+            Self::allocate_picking_storage(
+                &context,
+                texture.raw(),
+                ids.raw(),
+                renderbuffer.raw(),
+                &viewport,
+            );
 
             let fb = context
                 .create_framebuffer()
                 .map_err(|e| anyhow!("failed to create picking framebuffer: {}", e))?;
+            let fb = Handle::new(&context, fb, Context::delete_framebuffer);
 
-            context.bind_framebuffer(glow::FRAMEBUFFER, Some(fb));
+            context.bind_framebuffer(glow::FRAMEBUFFER, Some(fb.raw()));
 
             context.framebuffer_texture_2d(
                 glow::FRAMEBUFFER,
                 glow::COLOR_ATTACHMENT0,
                 glow::TEXTURE_2D,
-                Some(texture),
+                Some(texture.raw()),
+                0,
+            );
+            context.framebuffer_texture_2d(
+                glow::FRAMEBUFFER,
+                glow::COLOR_ATTACHMENT1,
+                glow::TEXTURE_2D,
+                Some(ids.raw()),
                 0,
             );
+            context.draw_buffers(&[glow::COLOR_ATTACHMENT0, glow::COLOR_ATTACHMENT1]);
 
             // make a depth buffer and the same size as the targetTexture
             context.framebuffer_renderbuffer(
                 glow::FRAMEBUFFER,
                 glow::DEPTH_ATTACHMENT,
                 glow::RENDERBUFFER,
-                Some(renderbuffer),
+                Some(renderbuffer.raw()),
             );
 
-            fb
+            (fb, texture, ids, renderbuffer)
         };
 
         // create the texture atlas
-        let atlas = unsafe {
-            context
-                .create_texture()
-                .map_err(|e| anyhow!("Failed to create a atlas_texture: {}", e))?
+        let image = images.load(BlockTexture::SRC).await?;
+        let (atlas, texture_array) = unsafe {
+            let atlas = Self::create_atlas_texture(&context, &image)?;
+            let texture_array = match backend {
+                TextureBackend::Atlas => None,
+                TextureBackend::Array => Some(Self::create_texture_array(
+                    &context,
+                    &image,
+                    texture_layout.uvs(),
+                )?),
+            };
+            (atlas, texture_array)
         };
 
-        // And upload image data to it
-        unsafe {
-            Self::load_image(&context, BlockTexture::SRC, |_gl, img, _img_src| {
-                context.bind_texture(glow::TEXTURE_2D, Some(atlas));
-                context.tex_image_2d_with_html_image(
-                    glow::TEXTURE_2D,
-                    0,
-                    glow::RGBA as _,
-                    glow::RGBA,
-                    glow::UNSIGNED_BYTE,
-                    &img,
-                );
-                context.tex_parameter_i32(
-                    glow::TEXTURE_2D,
-                    glow::TEXTURE_MIN_FILTER,
-                    glow::LINEAR_MIPMAP_LINEAR as _,
-                );
-                context.generate_mipmap(glow::TEXTURE_2D);
-                Ok(())
-            })
-            .await?;
-        }
-
-        let ui_renderer = unsafe { UiRenderer::new(&context)? };
+        let ui_renderer =
+            unsafe { UiRenderer::new(&context, &preprocessor, viewport.logical_size())? };
+        let post = unsafe { PostProcessor::new(&context, &preprocessor, &viewport)? };
 
         unsafe {
+            context.viewport(0, 0, viewport.width as _, viewport.height as _);
             context.enable(glow::DEPTH_TEST);
             context.enable(glow::BLEND);
             context.blend_func(glow::ONE, glow::ONE_MINUS_SRC_ALPHA);
@@ -177,359 +432,1158 @@ impl Renderer {
 
         Ok(Self {
             context,
+            output: Cell::new(output),
+            images,
             ui_renderer,
-            program,
-            picking_program,
+            post,
+            programs: RefCell::new(programs),
+            instance_buffer,
+            picking_program: RefCell::new(picking_program),
             picking_fb,
-            atlas,
+            picking_texture,
+            picking_ids,
+            picking_depth,
+            viewport: Cell::new(viewport),
+            sky_program: RefCell::new(sky_program),
+            sky_vertex_array,
+            atlas: RefCell::new(atlas),
+            texture_array: RefCell::new(texture_array),
+            texture_layout: RefCell::new(Rc::new(texture_layout)),
+            stats: Default::default(),
+            #[cfg(feature = "dev")]
+            shader_errors: Default::default(),
         })
     }
 
     pub fn create_mesh(&self, vertices: &[Vertex]) -> anyhow::Result<Mesh> {
-        let data: &[u8] = cast_slice(vertices);
-
+        let count = vertices.len() as i32;
         unsafe {
-            // First create the vertex array buffer, so that the bind buffer
-            // call gets recorded into the vertex array
-            let vao = self
-                .context
-                .create_vertex_array()
-                .map_err(|e| anyhow!("failed to create vertex array: {}", e))?;
-
-            self.context.bind_vertex_array(Some(vao));
-
-            let buffer = self
-                .context
-                .create_buffer()
-                .map_err(|e| anyhow!("failed to create buffer: {}", e))?;
+            let (vao, buffer) = self.create_vertex_storage(count, glow::STATIC_DRAW)?;
+            self.context
+                .buffer_sub_data_u8_slice(glow::ARRAY_BUFFER, 0, cast_slice(vertices));
+            Ok(Mesh::new(vao, buffer, count))
+        }
+    }
 
-            self.context.bind_buffer(glow::ARRAY_BUFFER, Some(buffer));
+    /// Replaces the vertices of `mesh`, which keeps its storage if they fit. Otherwise it grows
+    /// (with some slack for the next update) or moves to a larger range of its arena. The
+    /// storage never shrinks, a mesh that has fewer vertices than before keeps its capacity
+    pub fn update_mesh(&self, mesh: &mut Mesh, vertices: &[Vertex]) -> anyhow::Result<()> {
+        let count = vertices.len() as i32;
+        let data: &[u8] = cast_slice(vertices);
 
-            // Then we can upload data to the buffer
-            self.context
-                .buffer_data_u8_slice(glow::ARRAY_BUFFER, data, glow::STATIC_DRAW);
-
-            const ATTRIB_DATA: [i32; 4] = [3, 3, 2, 3];
-            let total_size = ATTRIB_DATA.iter().sum::<i32>() * FLOAT_SIZE;
-            let mut offset = 0;
-            for (index, size) in ATTRIB_DATA.iter().copied().enumerate() {
-                self.context.vertex_attrib_pointer_f32(
-                    index as u32,
-                    size,
-                    glow::FLOAT,
-                    false,
-                    total_size,
-                    offset * FLOAT_SIZE,
+        match &mut mesh.storage {
+            MeshStorage::Buffer {
+                buffer, capacity, ..
+            } => unsafe {
+                if let Some(grown) = Self::grown_capacity(*capacity, count) {
+                    *capacity = grown;
+                }
+                self.context
+                    .bind_buffer(glow::ARRAY_BUFFER, Some(buffer.raw()));
+                // Respecifying the storage orphans the previous one, so that this doesn't have
+                // to wait for draws that still read from it. It is always respecified with the
+                // full capacity, the vertices only fill its start
+                self.context.buffer_data_size(
+                    glow::ARRAY_BUFFER,
+                    *capacity * VERTEX_SIZE,
+                    glow::DYNAMIC_DRAW,
                 );
-
-                offset += size;
+                self.context
+                    .buffer_sub_data_u8_slice(glow::ARRAY_BUFFER, 0, data);
+            },
+            MeshStorage::Arena(allocation) => {
+                if let Some(grown) = Self::grown_capacity(allocation.capacity(), count) {
+                    let arena = allocation.arena().clone();
+                    // The previous range is given back once the new one is assigned
+                    *allocation = self.allocate(&arena, grown)?;
+                }
+                unsafe {
+                    self.context
+                        .bind_buffer(glow::ARRAY_BUFFER, Some(allocation.buffer()));
+                    self.context.buffer_sub_data_u8_slice(
+                        glow::ARRAY_BUFFER,
+                        allocation.first() * VERTEX_SIZE,
+                        data,
+                    );
+                }
             }
-
-            ATTRIB_DATA
-                .iter()
-                .enumerate()
-                .for_each(|(i, _)| self.context.enable_vertex_attrib_array(i as u32));
-            Ok(Mesh::new(vao, buffer, vertices.len() as i32))
         }
+
+        mesh.vertices_count = count;
+        Ok(())
     }
 
-    pub fn destroy_mesh(&self, mesh: Mesh) {
-        let Mesh { buffer, vao, .. } = mesh;
-        unsafe {
-            self.context.delete_vertex_array(vao);
-            self.context.delete_buffer(buffer);
-        }
+    /// Room for `count` vertices and some more, so that a mesh can grow a bit (eg. when a block
+    /// is placed) without moving every time
+    fn with_slack(count: i32) -> i32 {
+        count + (count / 4).max(4 * 36)
+    }
+
+    /// The capacity for `count` vertices, if the current `capacity` is too small for them
+    fn grown_capacity(capacity: i32, count: i32) -> Option<i32> {
+        (count > capacity).then(|| Self::with_slack(count))
     }
 
-    /// Safety: A mesh that has been destroyed cannot be used, so the caller has
-    /// to make sure, that the mesh is discarded afterwards
-    pub unsafe fn destroy_mesh_ref(&self, mesh: &Mesh) {
-        let Mesh { buffer, vao, .. } = mesh;
+    /// A vertex buffer with room for `capacity` vertices, together with a vertex array that
+    /// reads them. The buffer stays bound
+    unsafe fn create_vertex_storage(
+        &self,
+        capacity: i32,
+        usage: u32,
+    ) -> anyhow::Result<(Handle<VertexArray>, Handle<Buffer>)> {
+        // First create the vertex array buffer, so that the bind buffer
+        // call gets recorded into the vertex array
+        let vao = self
+            .context
+            .create_vertex_array()
+            .map_err(|e| anyhow!("failed to create vertex array: {}", e))?;
+        let vao = Handle::new(&self.context, vao, Context::delete_vertex_array);
+
+        self.context.bind_vertex_array(Some(vao.raw()));
+
+        let buffer = self
+            .context
+            .create_buffer()
+            .map_err(|e| anyhow!("failed to create buffer: {}", e))?;
+        let buffer = Handle::new(&self.context, buffer, Context::delete_buffer);
+
+        self.context
+            .bind_buffer(glow::ARRAY_BUFFER, Some(buffer.raw()));
+
+        // Then we can allocate the storage of the buffer
+        self.context
+            .buffer_data_size(glow::ARRAY_BUFFER, capacity * VERTEX_SIZE, usage);
+
+        const ATTRIB_DATA: [i32; 4] = [3, 3, 3, 3];
+        let total_size = ATTRIB_DATA.iter().sum::<i32>() * FLOAT_SIZE;
+        let mut offset = 0;
+        for (index, size) in ATTRIB_DATA.iter().copied().enumerate() {
+            self.context.vertex_attrib_pointer_f32(
+                index as u32,
+                size,
+                glow::FLOAT,
+                false,
+                total_size,
+                offset * FLOAT_SIZE,
+            );
+
+            offset += size;
+        }
 
-        self.context.delete_vertex_array(*vao);
-        self.context.delete_buffer(*buffer);
+        ATTRIB_DATA
+            .iter()
+            .enumerate()
+            .for_each(|(i, _)| self.context.enable_vertex_attrib_array(i as u32));
+        Ok((vao, buffer))
     }
 
-    pub unsafe fn compile_shader(
-        context: &Context,
+    /// The shader is deleted once it is dropped, which is deferred (by GL) until it is detached
+    /// from all programs, eg. they can be linked and the shaders dropped right away
+    pub(crate) unsafe fn compile_shader(
+        context: &Rc<Context>,
         shader_type: u32,
         source: &str,
-    ) -> anyhow::Result<Shader> {
+    ) -> anyhow::Result<Handle<Shader>> {
         let shader = context
             .create_shader(shader_type)
             .map_err(|e| anyhow!("Unable to create shader object: {}", e))?;
-        context.shader_source(shader, source);
-        context.compile_shader(shader);
+        let shader = Handle::new(context, shader, Context::delete_shader);
+        context.shader_source(shader.raw(), source);
+        context.compile_shader(shader.raw());
 
-        if context.get_shader_compile_status(shader) {
+        if context.get_shader_compile_status(shader.raw()) {
             Ok(shader)
         } else {
             bail!(
                 "failed to compile shader: {}",
-                context.get_shader_info_log(shader)
+                context.get_shader_info_log(shader.raw())
             );
         }
     }
 
-    pub async fn load_texture(&self, img_src: &'static str) -> anyhow::Result<Texture> {
+    pub async fn load_texture(&self, img_src: &'static str) -> anyhow::Result<Handle<Texture>> {
         let texture = unsafe {
-            self.context.create_texture().map_err(|e| {
+            let texture = self.context.create_texture().map_err(|e| {
                 anyhow!(
                     "failed to create texture for image: {}, error: {}",
                     img_src,
                     e
                 )
-            })?
+            })?;
+            Handle::new(&self.context, texture, Context::delete_texture)
         };
 
-        Self::load_image(&self.context, img_src, move |gl, img, _img_src| unsafe {
-            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
-            gl.tex_image_2d_with_html_image(
+        let image = self.images.load(img_src).await?;
+        unsafe {
+            self.context
+                .bind_texture(glow::TEXTURE_2D, Some(texture.raw()));
+            self.context.tex_image_2d(
                 glow::TEXTURE_2D,
                 0,
                 glow::RGBA as i32,
+                image.width as _,
+                image.height as _,
+                0,
                 glow::RGBA,
                 glow::UNSIGNED_BYTE,
-                &img,
+                Some(&image.pixels),
             );
-            gl.generate_mipmap(glow::TEXTURE_2D);
-            Ok(())
-        })
+            self.context.generate_mipmap(glow::TEXTURE_2D);
+        }
+
+        Ok(texture)
+    }
+
+    /// Uploads an atlas (with mipmaps) of the block textures
+    unsafe fn create_atlas_texture(
+        context: &Rc<Context>,
+        image: &Image,
+    ) -> anyhow::Result<Handle<Texture>> {
+        let texture = context
+            .create_texture()
+            .map_err(|e| anyhow!("Failed to create a atlas_texture: {}", e))?;
+        let texture = Handle::new(context, texture, Context::delete_texture);
+        context.bind_texture(glow::TEXTURE_2D, Some(texture.raw()));
+        context.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGBA as _,
+            image.width as _,
+            image.height as _,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            Some(&image.pixels),
+        );
+        context.tex_parameter_i32(
+            glow::TEXTURE_2D,
+            glow::TEXTURE_MIN_FILTER,
+            glow::LINEAR_MIPMAP_LINEAR as _,
+        );
+        context.generate_mipmap(glow::TEXTURE_2D);
+        Ok(texture)
+    }
+
+    /// Replaces the prebuilt atlas by one that is packed from the individual tile images in
+    /// `dir` (eg. `dir/brick_grey.png`). Meshes keep the texture coordinates they were built
+    /// with, so this has to happen before any of them are created
+    pub async fn load_tile_atlas(&self, dir: &str) -> anyhow::Result<()> {
+        let images = futures::future::try_join_all(BlockTexture::into_enum_iter().map(|texture| {
+            let src = format!("{}/{}.png", dir, texture.name());
+            async move {
+                self.images
+                    .load(&src)
+                    .await
+                    .map_err(|e| anyhow!("failed to read tile {}: {}", src, e))
+                    .map(|image| (texture.name(), image))
+            }
+        }))
         .await?;
 
+        let mut builder = AtlasBuilder::new(Self::ATLAS_EXTRUSION);
+        for (name, image) in images {
+            builder.add(name, image);
+        }
+        let atlas = builder.build()?;
+        self.use_atlas(&atlas.image, &atlas.layout)
+    }
+
+    /// Switches to the atlas `image` with the tiles at `layout`. All textures are created
+    /// first, so that nothing changes if one of them fails
+    pub(crate) fn use_atlas(&self, image: &Image, layout: &AtlasLayout) -> anyhow::Result<()> {
+        let texture_layout = TextureLayout::with_atlas(self.texture_layout().backend(), layout)?;
+        unsafe {
+            let atlas = Self::create_atlas_texture(&self.context, image)?;
+            let texture_array = match &*self.texture_array.borrow() {
+                Some(_) => Some(Self::create_texture_array(
+                    &self.context,
+                    image,
+                    texture_layout.uvs(),
+                )?),
+                None => None,
+            };
+
+            self.texture_layout.replace(Rc::new(texture_layout));
+            self.atlas.replace(atlas);
+            if texture_array.is_some() {
+                self.texture_array.replace(texture_array);
+            }
+            // The animated tiles might have moved as well
+            for program in self.programs.borrow().programs() {
+                Self::upload_animations(&self.context, program, &self.texture_layout());
+            }
+        }
+        Ok(())
+    }
+
+    /// Cuts every `BlockTexture` out of `atlas` (at `uvs`, see `TextureLayout::uvs`) and uploads
+    /// it as a layer of a texture array
+    unsafe fn create_texture_array(
+        context: &Rc<Context>,
+        atlas: &Image,
+        uvs: &[(glam::Vec2, glam::Vec2)],
+    ) -> anyhow::Result<Handle<Texture>> {
+        let size = glam::vec2(atlas.width as f32, atlas.height as f32);
+        let tiles = uvs
+            .iter()
+            .map(|(base, extend)| {
+                let base = (*base * size).round();
+                let extend = (*extend * size).round();
+                atlas.sub_image(TileRect {
+                    x: base.x as u32,
+                    y: base.y as u32,
+                    width: extend.x as u32,
+                    height: extend.y as u32,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let (width, height) = (tiles[0].width, tiles[0].height);
+        if tiles.iter().any(|t| t.width != width || t.height != height) {
+            bail!("all textures need to have the same size for a texture array");
+        }
+        let layers = tiles.len();
+        let pixels: Vec<u8> = tiles.into_iter().flat_map(|t| t.pixels).collect();
+
+        let texture = context
+            .create_texture()
+            .map_err(|e| anyhow!("failed to create texture array: {}", e))?;
+        let texture = Handle::new(context, texture, Context::delete_texture);
+        context.bind_texture(glow::TEXTURE_2D_ARRAY, Some(texture.raw()));
+        context.tex_image_3d(
+            glow::TEXTURE_2D_ARRAY,
+            0,
+            glow::RGBA as _,
+            width as _,
+            height as _,
+            layers as _,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            Some(&pixels),
+        );
+        context.tex_parameter_i32(
+            glow::TEXTURE_2D_ARRAY,
+            glow::TEXTURE_MIN_FILTER,
+            glow::LINEAR_MIPMAP_LINEAR as _,
+        );
+        context.generate_mipmap(glow::TEXTURE_2D_ARRAY);
         Ok(texture)
     }
 
-    pub async fn load_image<T: 'static>(
-        context: &Context,
-        img_src: &'static str,
-        cb: impl FnOnce(&Context, HtmlImageElement, &'static str) -> anyhow::Result<T>,
-    ) -> anyhow::Result<T> {
-        use futures::channel::oneshot::channel;
-        let (sender, receiver) = channel::<()>();
-
-        let img = HtmlImageElement::new()
-            .map_err(|e| anyhow!("failed to create image element {:?}", e))?;
-
-        let closure = {
-            Closure::once(Box::new(move || {
-                sender
-                    .send(())
-                    .expect("failed to send image completed method");
-            }) as Box<dyn FnOnce()>)
+    /// Binds the block textures of the active backend to the first texture unit
+    unsafe fn bind_block_textures(&self) {
+        self.context.active_texture(glow::TEXTURE0);
+        match &*self.texture_array.borrow() {
+            Some(texture_array) => self
+                .context
+                .bind_texture(glow::TEXTURE_2D_ARRAY, Some(texture_array.raw())),
+            None => self
+                .context
+                .bind_texture(glow::TEXTURE_2D, Some(self.atlas.borrow().raw())),
+        }
+    }
+
+    /// Compiles (or looks up) the program of a custom material, eg. `fragment` together with
+    /// `material::STANDARD_VERTEX_SHADER`. Like the built in shaders, they are preprocessed
+    /// (see `Preprocessor`), eg. they can include the snippets in `shaders/`
+    pub fn create_program(&self, vertex: &str, fragment: &str) -> anyhow::Result<ProgramId> {
+        if let Some(id) = self.programs.borrow().find(vertex, fragment) {
+            return Ok(id);
+        }
+
+        let layout = self.texture_layout();
+        let preprocessor = Preprocessor::new(layout.backend());
+        let program = unsafe {
+            Self::material_program(&self.context, &preprocessor, &layout, vertex, fragment)?
+        };
+        Ok(self.programs.borrow_mut().insert(vertex, fragment, program))
+    }
+
+    /// Loads the sources of all shaders that `reload_shaders` can recompile with `loader`, eg.
+    /// from `src/render/shaders` (natively) or from the dev server (in the browser)
+    #[cfg(feature = "dev")]
+    pub async fn fetch_shaders(loader: &dyn TextLoader) -> anyhow::Result<ShaderSources> {
+        const FILES: [&str; 8] = [
+            "solid.vert",
+            "solid.frag",
+            "picking.vert",
+            "picking.frag",
+            "sky.vert",
+            "sky.frag",
+            "ui.vert",
+            "ui.frag",
+        ];
+
+        let mut sources = ShaderSources::new();
+        let includes = preprocessor::INCLUDES.iter().map(|(file, _)| *file);
+        for file in FILES
+            .iter()
+            .copied()
+            .chain(PostProcessor::shader_files())
+            .chain(includes)
+        {
+            sources.insert(file.to_string(), loader.load(file).await?);
+        }
+        Ok(sources)
+    }
+
+    /// Recompiles the built in shaders (including the ones of the post processing, the UI and the
+    /// included snippets) and the materials using them from `sources`. A program that fails to
+    /// compile is kept as it was, its errors are logged and kept until the next reload, see
+    /// `shader_errors`
+    #[cfg(feature = "dev")]
+    pub fn reload_shaders(&self, sources: &ShaderSources) {
+        let source = |file: &str, code: &'static str| -> String {
+            sources.get(file).map_or(code, String::as_str).to_string()
         };
+        let layout = self.texture_layout();
+        let preprocessor = Preprocessor::new(layout.backend()).with_overrides(sources);
+        let mut errors = Vec::new();
+
+        unsafe {
+            let vertex = source("solid.vert", STANDARD_VERTEX_SHADER);
+            let fragment = source("solid.frag", STANDARD_FRAGMENT_SHADER);
+            let solid = with_define(STANDARD_FRAGMENT_SHADER, "SOLID_COLOR");
+
+            let mut programs = self.programs.borrow_mut();
+            for (id, vertex_code, fragment_code) in programs.sources() {
+                // Custom materials only change with the standard vertex shader
+                let (vertex_file, vertex_code) = if vertex_code == STANDARD_VERTEX_SHADER {
+                    ("solid.vert", vertex.clone())
+                } else {
+                    ("custom", vertex_code)
+                };
+                let (fragment_file, fragment_code) = if fragment_code == STANDARD_FRAGMENT_SHADER {
+                    ("solid.frag", fragment.clone())
+                } else if fragment_code == solid {
+                    ("solid.frag", with_define(&fragment, "SOLID_COLOR"))
+                } else {
+                    ("custom", fragment_code)
+                };
+
+                let program = Self::material_program(
+                    &self.context,
+                    &preprocessor,
+                    &layout,
+                    &vertex_code,
+                    &fragment_code,
+                );
+                match program {
+                    Ok(program) => programs.replace(id, program),
+                    Err(e) => errors.push(format!("{} + {}: {}", vertex_file, fragment_file, e)),
+                }
+            }
 
-        img.set_onload(Some(closure.as_ref().unchecked_ref()));
-        img.set_src(img_src);
+            let picking = Self::compile_program(
+                &self.context,
+                &preprocessor,
+                &source("picking.vert", PICKING_VERTEX_CODE),
+                &source("picking.frag", PICKING_FRAGMENT_CODE),
+            );
+            match picking {
+                Ok(program) => *self.picking_program.borrow_mut() = program,
+                Err(e) => errors.push(format!("picking.vert + picking.frag: {}", e)),
+            }
 
-        // wait for completion
-        let _ = receiver.await?;
+            let sky = Self::compile_program(
+                &self.context,
+                &preprocessor,
+                &source("sky.vert", SKY_VERTEX_CODE),
+                &source("sky.frag", SKY_FRAGMENT_CODE),
+            );
+            match sky {
+                Ok(program) => *self.sky_program.borrow_mut() = program,
+                Err(e) => errors.push(format!("sky.vert + sky.frag: {}", e)),
+            }
 
-        cb(context, img, img_src)
+            self.post
+                .reload_shaders(&self.context, &preprocessor, sources, &mut errors);
+            self.ui_renderer
+                .reload_shaders(&self.context, &preprocessor, sources, &mut errors);
+        }
+
+        for error in &errors {
+            log!("failed to reload shaders {}", error);
+        }
+        self.shader_errors.replace(errors);
     }
 
-    pub unsafe fn link_program(
-        context: &Context,
-        vert_shader: Shader,
-        frag_shader: Shader,
-    ) -> anyhow::Result<Program> {
+    /// Errors of the shaders that failed to compile in the last `reload_shaders`
+    #[cfg(feature = "dev")]
+    pub fn shader_errors(&self) -> Vec<String> {
+        self.shader_errors.borrow().clone()
+    }
+
+    unsafe fn material_program(
+        context: &Rc<Context>,
+        preprocessor: &Preprocessor,
+        layout: &TextureLayout,
+        vertex: &str,
+        fragment: &str,
+    ) -> anyhow::Result<Handle<Program>> {
+        let program = Self::compile_program(context, preprocessor, vertex, fragment)?;
+        Self::upload_animations(context, program.raw(), layout);
+        Ok(program)
+    }
+
+    /// The frames of the animated textures never change, so they are uploaded once and the
+    /// shader selects the current frame based on the `time` uniform
+    unsafe fn upload_animations(context: &Context, program: Program, layout: &TextureLayout) {
+        let animations: Vec<_> = BlockTexture::into_enum_iter()
+            .filter_map(|texture| Some((texture.animation()?, texture)))
+            .take(MAX_ANIMATIONS)
+            .collect();
+
+        let mut rects = Vec::with_capacity(animations.len() * 4);
+        let mut layers = Vec::with_capacity(animations.len());
+        let mut frames = vec![0.0; MAX_ANIMATIONS * MAX_FRAMES * 3];
+        let mut frame_counts = Vec::with_capacity(animations.len());
+        for (i, (animation, texture)) in animations.iter().enumerate() {
+            let (base, extend) = layout.uv(texture);
+            rects.extend_from_slice(base.as_ref());
+            rects.extend_from_slice(extend.as_ref());
+            layers.push(texture.layer() as i32);
+
+            let mut end = 0.0;
+            for (j, frame) in animation.iter().take(MAX_FRAMES).enumerate() {
+                end += frame.duration;
+                let k = (i * MAX_FRAMES + j) * 3;
+                frames[k..k + 3].copy_from_slice(&[frame.offset.x, frame.offset.y, end]);
+            }
+            frame_counts.push(animation.len().min(MAX_FRAMES) as i32);
+        }
+
+        context.use_program(Some(program));
+        let loc = context.get_uniform_location(program, "animation_count");
+        context.uniform_1_i32(loc.as_ref(), animations.len() as i32);
+        if animations.is_empty() {
+            return;
+        }
+        let loc = context.get_uniform_location(program, "animation_rects");
+        context.uniform_4_f32_slice(loc.as_ref(), &rects);
+        let loc = context.get_uniform_location(program, "animation_layers");
+        context.uniform_1_i32_slice(loc.as_ref(), &layers);
+        let loc = context.get_uniform_location(program, "animation_frames");
+        context.uniform_3_f32_slice(loc.as_ref(), &frames);
+        let loc = context.get_uniform_location(program, "animation_frame_counts");
+        context.uniform_1_i32_slice(loc.as_ref(), &frame_counts);
+    }
+
+    /// Preprocesses, compiles and links the shaders
+    pub(crate) unsafe fn compile_program(
+        context: &Rc<Context>,
+        preprocessor: &Preprocessor,
+        vertex: &str,
+        fragment: &str,
+    ) -> anyhow::Result<Handle<Program>> {
+        let vertex = preprocessor.process(vertex)?;
+        let fragment = preprocessor.process(fragment)?;
+        let vert_shader = Self::compile_shader(context, glow::VERTEX_SHADER, &vertex)?;
+        let frag_shader = Self::compile_shader(context, glow::FRAGMENT_SHADER, &fragment)?;
+        Self::link_program(context, &vert_shader, &frag_shader)
+    }
+
+    pub(crate) unsafe fn link_program(
+        context: &Rc<Context>,
+        vert_shader: &Handle<Shader>,
+        frag_shader: &Handle<Shader>,
+    ) -> anyhow::Result<Handle<Program>> {
         let program = context
             .create_program()
             .map_err(|e| anyhow!("Unable to create shader object: {}", e))?;
+        let program = Handle::new(context, program, Context::delete_program);
 
-        context.attach_shader(program, vert_shader);
-        context.attach_shader(program, frag_shader);
-        context.link_program(program);
+        context.attach_shader(program.raw(), vert_shader.raw());
+        context.attach_shader(program.raw(), frag_shader.raw());
+        context.link_program(program.raw());
 
-        if context.get_program_link_status(program) {
+        if context.get_program_link_status(program.raw()) {
             Ok(program)
         } else {
             bail!(
                 "failed to link program: {}",
-                context.get_program_info_log(program)
+                context.get_program_info_log(program.raw())
             );
         }
     }
 
-    pub fn start_frame<'a>(&self) -> (RenderTask<'a>, UiFrame) {
+    pub fn start_frame<'a>(&self, camera: &Camera) -> (RenderTask<'a>, UiFrame) {
         (
             RenderTask {
                 meshes: Default::default(),
+                batches: Default::default(),
+                frustum: Frustum::from_matrix(&camera.projection_view),
+                culled: 0,
+                occluded: 0,
             },
             UiFrame::new(),
         )
     }
 
-    pub fn get_atlas(&self) -> &Texture {
-        &self.atlas
+    pub fn viewport(&self) -> Viewport {
+        self.viewport.get()
+    }
+
+    /// Adapts all render targets to a new drawing buffer size
+    pub fn resize(&self, viewport: Viewport) {
+        self.viewport.set(viewport);
+        unsafe {
+            Self::allocate_picking_storage(
+                &self.context,
+                self.picking_texture.raw(),
+                self.picking_ids.raw(),
+                self.picking_depth.raw(),
+                &viewport,
+            );
+            self.post.resize(&self.context, &viewport);
+            self.context
+                .viewport(0, 0, viewport.width as _, viewport.height as _);
+        }
+        self.ui_renderer.resize(viewport.logical_size());
+    }
+
+    pub fn set_post_effect(&self, effect: PostEffect, enabled: bool) {
+        self.post.set_enabled(effect, enabled);
+    }
+
+    unsafe fn allocate_picking_storage(
+        context: &Context,
+        texture: Texture,
+        ids: Texture,
+        renderbuffer: Renderbuffer,
+        viewport: &Viewport,
+    ) {
+        let (width, height) = (viewport.width as i32, viewport.height as i32);
+
+        context.bind_texture(glow::TEXTURE_2D, Some(texture));
+        context.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGBA as i32,
+            width,
+            height,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            None,
+        );
+
+        context.bind_texture(glow::TEXTURE_2D, Some(ids));
+        context.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::R32UI as i32,
+            width,
+            height,
+            0,
+            glow::RED_INTEGER,
+            glow::UNSIGNED_INT,
+            None,
+        );
+
+        context.bind_renderbuffer(glow::RENDERBUFFER, Some(renderbuffer));
+        context.renderbuffer_storage(glow::RENDERBUFFER, glow::DEPTH_COMPONENT16, width, height);
+    }
+
+    /// Reads back the last rendered frame (top row first)
+    pub fn read_pixels(&self) -> anyhow::Result<Image> {
+        let viewport = self.viewport.get();
+        let mut pixels = vec![0u8; (viewport.width * viewport.height * 4) as usize];
+        unsafe {
+            self.context
+                .bind_framebuffer(glow::FRAMEBUFFER, self.output.get());
+            self.context.read_pixels(
+                0,
+                0,
+                viewport.width as _,
+                viewport.height as _,
+                glow::RGBA,
+                glow::UNSIGNED_BYTE,
+                PixelPackData::Slice(&mut pixels),
+            );
+        }
+
+        // GL starts at the bottom row
+        let row = (viewport.width * 4) as usize;
+        let pixels = pixels.chunks(row).rev().flatten().copied().collect();
+        Image::from_rgba(viewport.width, viewport.height, pixels)
+    }
+
+    /// Renders a frame with `render` (eg. a `Game::render`) and reads it back. With a `scale`
+    /// above 1, it is rendered offscreen at `scale` times the size of the viewport instead, eg.
+    /// for supersampled screenshots. The UI keeps its layout, since it is sized in logical pixels
+    pub fn capture(&self, scale: u32, render: impl FnOnce(&Self)) -> anyhow::Result<Image> {
+        if scale <= 1 {
+            render(self);
+            return self.read_pixels();
+        }
+
+        let viewport = self.viewport.get();
+        let scaled = Viewport {
+            width: viewport.width * scale,
+            height: viewport.height * scale,
+            dpr: viewport.dpr * scale as f32,
+        };
+        let max_size = unsafe { self.context.get_parameter_i32(glow::MAX_RENDERBUFFER_SIZE) };
+        if scaled.width.max(scaled.height) > max_size as u32 {
+            bail!(
+                "can not capture {}x{} pixels, the maximum size is {}",
+                scaled.width,
+                scaled.height,
+                max_size
+            );
+        }
+
+        // The renderbuffers are only kept, so that they are deleted together with the framebuffer
+        let (framebuffer, _renderbuffers) = unsafe { self.create_capture_target(&scaled)? };
+        let output = self.output.replace(Some(framebuffer.raw()));
+        self.resize(scaled);
+
+        render(self);
+        let image = self.read_pixels();
+
+        self.output.set(output);
+        self.resize(viewport);
+
+        image
+    }
+
+    /// A framebuffer like the default one (color and depth) of the size of `viewport`
+    unsafe fn create_capture_target(
+        &self,
+        viewport: &Viewport,
+    ) -> anyhow::Result<(Handle<Framebuffer>, [Handle<Renderbuffer>; 2])> {
+        let gl = &self.context;
+        let framebuffer = gl
+            .create_framebuffer()
+            .map_err(|e| anyhow!("failed to create capture framebuffer: {}", e))?;
+        let framebuffer = Handle::new(gl, framebuffer, Context::delete_framebuffer);
+        let color = gl
+            .create_renderbuffer()
+            .map_err(|e| anyhow!("failed to create capture color renderbuffer: {}", e))?;
+        let color = Handle::new(gl, color, Context::delete_renderbuffer);
+        let depth = gl
+            .create_renderbuffer()
+            .map_err(|e| anyhow!("failed to create capture depth renderbuffer: {}", e))?;
+        let depth = Handle::new(gl, depth, Context::delete_renderbuffer);
+
+        gl.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer.raw()));
+        for (renderbuffer, format, attachment) in [
+            (&color, glow::RGBA8, glow::COLOR_ATTACHMENT0),
+            (&depth, glow::DEPTH_COMPONENT24, glow::DEPTH_ATTACHMENT),
+        ] {
+            gl.bind_renderbuffer(glow::RENDERBUFFER, Some(renderbuffer.raw()));
+            gl.renderbuffer_storage(
+                glow::RENDERBUFFER,
+                format,
+                viewport.width as _,
+                viewport.height as _,
+            );
+            gl.framebuffer_renderbuffer(
+                glow::FRAMEBUFFER,
+                attachment,
+                glow::RENDERBUFFER,
+                Some(renderbuffer.raw()),
+            );
+        }
+
+        let status = gl.check_framebuffer_status(glow::FRAMEBUFFER);
+        gl.bind_framebuffer(glow::FRAMEBUFFER, None);
+        if status != glow::FRAMEBUFFER_COMPLETE {
+            bail!("capture framebuffer is incomplete: {:#x}", status);
+        }
+
+        Ok((framebuffer, [color, depth]))
+    }
+
+    /// The atlas texture, which is only valid until the atlas is replaced (eg. by a resource
+    /// pack)
+    pub fn get_atlas(&self) -> Texture {
+        self.atlas.borrow().raw()
     }
 
-    pub fn pick<'a>(&self, task: &RenderTask<'a>, camera: &Camera) -> Option<(glam::Vec3, Face)> {
+    /// The texture coordinates meshes have to be built with. They change when the atlas is
+    /// replaced (eg. by a resource pack), meshes that were built before have to be rebuilt then
+    pub fn texture_layout(&self) -> Rc<TextureLayout> {
+        Rc::clone(&self.texture_layout.borrow())
+    }
+
+    pub fn stats(&self) -> RenderStats {
+        self.stats.get()
+    }
+
+    /// Returns the world space location of the block in the center of the screen
+    pub fn pick<'a>(&self, task: &RenderTask<'a>, camera: &Camera) -> Option<glam::Vec3> {
         // The opengl part of the picking procedure is unsafe due to glow
         let data = unsafe {
             self.context
-                .bind_framebuffer(glow::FRAMEBUFFER, Some(self.picking_fb));
+                .bind_framebuffer(glow::FRAMEBUFFER, Some(self.picking_fb.raw()));
+            // An id of 0 means nothing has been hit. Integer attachments can't be cleared by
+            // `clear`, so each one is cleared on its own
             self.context
-                .clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
+                .clear_buffer_f32_slice(glow::COLOR, 0, &[0.0; 4]);
+            self.context.clear_buffer_u32_slice(glow::COLOR, 1, &[0; 4]);
+            self.context.clear(glow::DEPTH_BUFFER_BIT);
+            self.context.disable(glow::BLEND);
 
-            self.context.use_program(Some(self.picking_program));
+            let program = self.picking_program.borrow().raw();
+            self.context.use_program(Some(program));
 
             let loc = self
                 .context
-                .get_uniform_location(self.picking_program, "view_projection");
+                .get_uniform_location(program, "view_projection");
             self.context.uniform_matrix_4_f32_slice(
                 loc.as_ref(),
                 false,
                 camera.projection_view.as_ref(),
             );
 
-            for (mesh, _transform, _) in task.meshes.iter() {
-                // TODO: Model matrix
-                self.context.bind_vertex_array(Some(mesh.vao));
+            let model_loc = self.context.get_uniform_location(program, "model");
+            let mesh_id_loc = self.context.get_uniform_location(program, "mesh_id");
+            let alpha_cutoff_loc = self.context.get_uniform_location(program, "alpha_cutoff");
+
+            self.bind_block_textures();
+
+            // The index of the draw call (+ 1) is written to the id attachment
+            for (
+                i,
+                DrawCall {
+                    mesh,
+                    transform,
+                    layer,
+                    ..
+                },
+            ) in task
+                .meshes
+                .iter()
+                .enumerate()
+                .filter(|(_, call)| call.pickable)
+            {
+                let model = transform.unwrap_or(glam::Mat4::IDENTITY);
+                let alpha_cutoff = match layer {
+                    RenderLayer::Cutout => 0.5,
+                    _ => 0.0,
+                };
+                self.context
+                    .uniform_1_f32(alpha_cutoff_loc.as_ref(), alpha_cutoff);
+                self.context
+                    .uniform_matrix_4_f32_slice(model_loc.as_ref(), false, model.as_ref());
+                self.context
+                    .uniform_1_u32(mesh_id_loc.as_ref(), i as u32 + 1);
+
+                self.context.bind_vertex_array(Some(mesh.vao()));
 
                 self.context
-                    .draw_arrays(glow::TRIANGLES, 0, mesh.vertices_count)
+                    .draw_arrays(glow::TRIANGLES, mesh.first(), mesh.vertices_count)
             }
 
-            // read back pixel (probably a bad time for that)
+            // read back the pixel in the center of the screen (probably a bad time for that)
             // NOTE: Maybe do 2x2 area and like avg over that
+            let viewport = self.viewport.get();
+            let (x, y) = ((viewport.width / 2) as _, (viewport.height / 2) as _);
             let mut data = [0u8; 4];
             self.context.read_pixels(
-                300,
-                200,
+                x,
+                y,
                 1,
                 1,
                 glow::RGBA,
                 glow::UNSIGNED_BYTE,
                 PixelPackData::Slice(&mut data),
             );
-            data
+            // RGBA_INTEGER is the only format that is guaranteed to be readable
+            let mut id = [0u32; 4];
+            self.context.read_buffer(glow::COLOR_ATTACHMENT1);
+            self.context.read_pixels(
+                x,
+                y,
+                1,
+                1,
+                glow::RGBA_INTEGER,
+                glow::UNSIGNED_INT,
+                PixelPackData::Slice(bytemuck::cast_slice_mut(&mut id)),
+            );
+            self.context.read_buffer(glow::COLOR_ATTACHMENT0);
+
+            self.context.enable(glow::BLEND);
+            (data, id[0])
         };
 
         // -> by now data should be the 4 pixel value
         // Do some simple calculation to figure out the coordinate
-        if data[3] /*eg. alpha*/ != 0 {
+        let (data, id) = data;
+        if id != 0 {
             let loc = glam::UVec3::new(data[0] as _, data[1] as _, data[2] as _).as_vec3();
             let loc = loc * ((CHUNK_SIZE as f32 - 1.0) / 255.0f32);
-
-            // And also figure out the face
-            Face::FACES
-                .iter()
-                .map(|f| (f, f.normal()))
-                // First of we only consider front-facing faces wrt. to camera direction, eg. with an angle between 90° and 180°
-                // since |normal| = |view_dir| = 1 => cos(angle) = normal.dot(view_dir)
-                // and for 90° <= angle <= 180° => -1 <= cos(angle) = normal.dot(view_dir) <= 0
-                .filter(|(_f, normal)| normal.dot(camera.dir) <= 0.0f32)
-                .filter_map(|(f, normal)| {
-                    // Next we need to find the hit point of the face plane (d (point on plane), n (normal)) and the ray (o (camera pos), rd (camera dir))
-                    // The plane is given by x.dot(n) = d.dot(n) and the ray by x = o + t * rd
-                    // plugging that into the plane equation yields a result for t = ((d - o) ∙ n) / (rd ∙ n) (where ∙ denotes the dot product)
-                    let d = loc + normal * 0.5f32;
-                    let divisor = camera.dir.dot(normal);
-                    // divisor == 0 indicates parallel dir -> eg. no collision or embedded (edge case does need to handle)
-                    if divisor == 0.0f32 {
-                        return None;
-                    }
-                    let t = (d - camera.pos).dot(normal) / divisor;
-
-                    // We then obtain the hit point through x = o + t * rd
-                    let x = camera.pos + t * camera.dir;
-                    // log!("found hit-point {} for loc {} and face {:?}", x, loc, f);
-
-                    // We can then obtain the u/v coordinates of the point in the plane trough the parametric form of the plane equation
-                    // x = d + u ∙ e0 + v ∙ e1 (where e0 and e1 are the edge vectors of the quad)
-                    let e0 = f.orthogonal();
-                    let e1 = e0.cross(normal).normalize();
-                    // Since e0 ∙ e1 = 0 (eg. orthogonal) and e0 ∙ e0 = e1 ∙ e1 = 1
-                    // we can compute u = (x - d) * e0 and v = (x - d) * e1
-                    let u = (x - d).dot(e0);
-                    let v = (x - d).dot(e1);
-                    // log!("[{:?}] u: {}, v: {}", f, u, v);
-                    // Then the hit-point lies inside of the quad if u, v ∈ [-0.5;0.5]
-                    (-0.5 <= u && u <= 0.5 && -0.5 <= v && v <= 0.5).then(|| (f, x))
-                })
-                .min_by(|(_, a), (_, b)| {
-                    a.distance_squared(camera.pos)
-                        .partial_cmp(&b.distance_squared(camera.pos))
-                        .unwrap_or(Ordering::Equal)
-                })
-                .map(|(f, _)| (loc, f.clone()))
+            // transform the location into world space
+            let DrawCall { transform, .. } = task.meshes.get(id as usize - 1)?;
+            let loc = transform
+                .map(|t| t.transform_point3(loc))
+                .unwrap_or(loc)
+                .round();
+            Some(loc)
         } else {
             None
         }
     }
 
-    pub fn render<'a>(
-        &self,
-        task: RenderTask<'a>,
-        frame: UiFrame,
-        camera: &Camera,
-        light_dir: &glam::Vec3,
-    ) {
-        // Main Pass
-        unsafe {
-            self.context.bind_framebuffer(glow::FRAMEBUFFER, None);
+    unsafe fn render_sky(&self, camera: &Camera, sky: &Sky) {
+        let gl = &self.context;
+
+        // The sky is always behind everything else, so it neither needs depth testing
+        // nor should it write to the depth buffer
+        gl.disable(glow::DEPTH_TEST);
+        gl.depth_mask(false);
+
+        let program = self.sky_program.borrow().raw();
+        gl.use_program(Some(program));
+
+        let loc = gl.get_uniform_location(program, "inv_view_projection");
+        gl.uniform_matrix_4_f32_slice(
+            loc.as_ref(),
+            false,
+            camera.projection_view.inverse().as_ref(),
+        );
+
+        let vec3_uniforms = [
+            ("view_pos", camera.pos),
+            ("sun_dir", sky.sun_dir()),
+            ("zenith_color", sky.zenith_color()),
+            ("horizon_color", sky.horizon_color()),
+            ("sun_color", sky.sun_color()),
+        ];
+        for (name, value) in vec3_uniforms.iter() {
+            let loc = gl.get_uniform_location(program, name);
+            gl.uniform_3_f32_slice(loc.as_ref(), value.as_ref());
+        }
 
-            self.context
-                .clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
+        gl.bind_vertex_array(Some(self.sky_vertex_array.raw()));
+        gl.draw_arrays(glow::TRIANGLES, 0, 3);
 
-            self.context.use_program(Some(self.program));
+        gl.depth_mask(true);
+        gl.enable(glow::DEPTH_TEST);
+    }
 
-            let loc = self
-                .context
-                .get_uniform_location(self.program, "view_projection");
-            self.context.uniform_matrix_4_f32_slice(
-                loc.as_ref(),
+    /// Draws `mesh` (whose vertex array has to be bound) once per instance
+    unsafe fn draw_instanced(&self, mesh: &Mesh, instances: &[Instance]) {
+        let gl = &self.context;
+        gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.instance_buffer.raw()));
+        gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, cast_slice(instances), glow::STREAM_DRAW);
+
+        // The transform takes up one location per column, followed by the color
+        const INSTANCE_LOCATION: u32 = 4;
+        const INSTANCE_ATTRIBS: u32 = 5;
+        let stride = std::mem::size_of::<Instance>() as i32;
+        for i in 0..INSTANCE_ATTRIBS {
+            let location = INSTANCE_LOCATION + i;
+            gl.vertex_attrib_pointer_f32(
+                location,
+                4,
+                glow::FLOAT,
                 false,
-                camera.projection_view.as_ref(),
+                stride,
+                4 * FLOAT_SIZE * i as i32,
             );
+            gl.vertex_attrib_divisor(location, 1);
+            gl.enable_vertex_attrib_array(location);
+        }
 
-            {
-                let loc = self.context.get_uniform_location(self.program, "light_dir");
-                // TODO: Make that dynamic
-                // const SUN: glam::Vec3 = glam::const_vec3!([10.0, 10.0, 10.0]);
-                self.context
-                    .uniform_3_f32_slice(loc.as_ref(), light_dir.as_ref())
-            }
+        gl.draw_arrays_instanced(
+            glow::TRIANGLES,
+            mesh.first(),
+            mesh.vertices_count,
+            instances.len() as i32,
+        );
 
-            {
-                let loc = self.context.get_uniform_location(self.program, "view_pos");
-                self.context
-                    .uniform_3_f32_slice(loc.as_ref(), camera.pos.as_ref())
-            }
+        // The vertex array belongs to the mesh, which might still be drawn without instances
+        for i in 0..INSTANCE_ATTRIBS {
+            gl.disable_vertex_attrib_array(INSTANCE_LOCATION + i);
+        }
+    }
 
-            let loc = self.context.get_uniform_location(self.program, "model");
+    /// Uses `program` and sets the uniforms that are the same for all materials
+    unsafe fn use_material_program(&self, program: Program, camera: &Camera, sky: &Sky, time: f32) {
+        let gl = &self.context;
+        gl.use_program(Some(program));
 
-            self.context.active_texture(glow::TEXTURE0);
+        let loc = gl.get_uniform_location(program, "view_projection");
+        gl.uniform_matrix_4_f32_slice(loc.as_ref(), false, camera.projection_view.as_ref());
 
-            // TODO: Atlas
-            self.context
-                .bind_texture(glow::TEXTURE_2D, Some(self.atlas));
+        let loc = gl.get_uniform_location(program, "view_pos");
+        gl.uniform_3_f32_slice(loc.as_ref(), camera.pos.as_ref());
 
-            let solid_color_loc = self
-                .context
-                .get_uniform_location(self.program, "solid_color");
+        let loc = gl.get_uniform_location(program, "time");
+        gl.uniform_1_f32(loc.as_ref(), time);
 
-            for (mesh, transform, material) in task.meshes.into_iter() {
-                self.context.bind_vertex_array(Some(mesh.vao));
+        {
+            let loc = gl.get_uniform_location(program, "light_dir");
+            gl.uniform_3_f32_slice(loc.as_ref(), sky.light_dir().as_ref());
 
-                let model = match transform {
-                    Some(transform) => transform,
-                    None => glam::Mat4::IDENTITY,
-                };
-                self.context
-                    .uniform_matrix_4_f32_slice(loc.as_ref(), false, model.as_ref());
+            let loc = gl.get_uniform_location(program, "light_color");
+            gl.uniform_3_f32_slice(loc.as_ref(), sky.light_color().as_ref());
 
-                let color = match material {
-                    Material::Atlas => glam::Vec4::ZERO,
-                    Material::Solid(color) => color,
-                };
+            let loc = gl.get_uniform_location(program, "ambient_color");
+            gl.uniform_3_f32_slice(loc.as_ref(), sky.ambient_color().as_ref());
+        }
+
+        {
+            // Fog starts at half the render distance and hides the edge of the world
+            let fog_end = (RENDER_DISTANCE * CHUNK_SIZE) as f32;
+            let fog_start = 0.5 * fog_end;
+
+            let loc = gl.get_uniform_location(program, "fog_color");
+            gl.uniform_3_f32_slice(loc.as_ref(), sky.fog_color().as_ref());
+
+            let loc = gl.get_uniform_location(program, "fog_start");
+            gl.uniform_1_f32(loc.as_ref(), fog_start);
+
+            let loc = gl.get_uniform_location(program, "fog_end");
+            gl.uniform_1_f32(loc.as_ref(), fog_end);
+        }
+    }
+
+    unsafe fn begin_layer(&self, layer: RenderLayer, program: Program) {
+        let gl = &self.context;
+        let (alpha_cutoff, translucent) = match layer {
+            RenderLayer::Opaque => (0.0, false),
+            RenderLayer::Cutout => (0.5, false),
+            RenderLayer::Translucent => (0.01, true),
+        };
+
+        // Translucent geometry is not part of the depth and normals the post processing sees
+        self.post.write_normals(gl, !translucent);
+        if translucent {
+            // Translucent geometry is depth tested against, but does not occlude anything
+            gl.enable(glow::BLEND);
+            gl.depth_mask(false);
+        } else {
+            gl.disable(glow::BLEND);
+            gl.depth_mask(true);
+        }
+
+        let loc = gl.get_uniform_location(program, "alpha_cutoff");
+        gl.uniform_1_f32(loc.as_ref(), alpha_cutoff);
+        let loc = gl.get_uniform_location(program, "translucent");
+        gl.uniform_1_i32(loc.as_ref(), translucent as i32);
+    }
+
+    /// `time` is the total elapsed time in seconds, eg. for animations
+    pub fn render<'a>(
+        &self,
+        task: RenderTask<'a>,
+        frame: UiFrame,
+        camera: &Camera,
+        sky: &Sky,
+        time: f32,
+    ) {
+        // Sky Pass, the scene is rendered offscreen for the post processing
+        unsafe {
+            self.post.begin_scene(&self.context, sky.horizon_color());
+            self.render_sky(camera, sky);
+        }
+
+        // Main Pass
+        unsafe {
+            self.bind_block_textures();
+
+            let mut stats = RenderStats {
+                meshes_submitted: task.meshes.len() as u32,
+                meshes_culled: task.culled,
+                meshes_occluded: task.occluded,
+                triangles: 0,
+            };
+
+            // Opaque geometry first, then cutouts and at last the translucent geometry back to
+            // front (the faces within a mesh are sorted by its owner, eg. `World::update`). The
+            // first two are grouped by program and then by material (and otherwise kept in push
+            // order), to switch between them as little as possible
+            let mut draws = task.meshes;
+            draws.sort_by(|a, b| {
+                a.layer.cmp(&b.layer).then_with(|| match a.layer {
+                    RenderLayer::Translucent => b
+                        .center
+                        .distance_squared(camera.pos)
+                        .partial_cmp(&a.center.distance_squared(camera.pos))
+                        .unwrap_or(Ordering::Equal),
+                    _ => a
+                        .material
+                        .program()
+                        .cmp(&b.material.program())
+                        .then_with(|| a.material.key().cmp(&b.material.key())),
+                })
+            });
+
+            let programs = self.programs.borrow();
+            let mut current_program = None;
+            let mut current_layer = None;
+            let mut current_material = None;
+            let (mut model_loc, mut instanced_loc) = (None, None);
+            for DrawCall {
+                mesh,
+                transform,
+                material,
+                layer,
+                instances,
+                ..
+            } in draws.iter()
+            {
+                let copies = instances.as_ref().map_or(1, |instances| instances.len());
+                stats.triangles += mesh.vertices_count as u32 / 3 * copies as u32;
+
+                let program = programs.get(material.program());
+                if current_program != Some(program) {
+                    self.use_material_program(program, camera, sky, time);
+                    model_loc = self.context.get_uniform_location(program, "model");
+                    instanced_loc = self.context.get_uniform_location(program, "instanced");
+                    current_program = Some(program);
+                    current_layer = None;
+                    current_material = None;
+                }
+
+                if current_layer != Some(*layer) {
+                    self.begin_layer(*layer, program);
+                    current_layer = Some(*layer);
+                }
+
+                if current_material != Some(material) {
+                    material.apply(&self.context, &programs);
+                    current_material = Some(material);
+                }
+
+                self.context.bind_vertex_array(Some(mesh.vao()));
+
+                if let Some(instances) = instances {
+                    self.context.uniform_1_i32(instanced_loc.as_ref(), 1);
+                    self.draw_instanced(mesh, instances);
+                    self.context.uniform_1_i32(instanced_loc.as_ref(), 0);
+                    continue;
+                }
+
+                let model = transform.unwrap_or(glam::Mat4::IDENTITY);
                 self.context
-                    .uniform_4_f32_slice(solid_color_loc.as_ref(), color.as_ref());
+                    .uniform_matrix_4_f32_slice(model_loc.as_ref(), false, model.as_ref());
 
                 self.context
-                    .draw_arrays(glow::TRIANGLES, 0, mesh.vertices_count)
+                    .draw_arrays(glow::TRIANGLES, mesh.first(), mesh.vertices_count)
             }
+
+            self.stats.set(stats);
+
+            // Restore the default state
+            self.context.depth_mask(true);
+            self.context.enable(glow::BLEND);
+        }
+
+        // Post Processing, which ends up on the screen
+        unsafe {
+            self.post.render(
+                &self.context,
+                &self.viewport.get(),
+                camera,
+                self.output.get(),
+            );
         }
 
         // Ui Pass
@@ -538,3 +1592,77 @@ impl Renderer {
         }
     }
 }
+
+/// Figures out which face of the block at `loc` the camera is looking at, where the block
+/// consists of `boxes` (relative to the block center). Returns the face and the box it belongs to
+pub fn pick_face(camera: &Camera, loc: glam::Vec3, boxes: &[Aabb]) -> Option<(Face, Aabb)> {
+    boxes
+        .iter()
+        .flat_map(|b| Face::FACES.iter().map(move |f| (b, f, f.normal())))
+        // First of we only consider front-facing faces wrt. to camera direction, eg. with an angle between 90° and 180°
+        // since |normal| = |view_dir| = 1 => cos(angle) = normal.dot(view_dir)
+        // and for 90° <= angle <= 180° => -1 <= cos(angle) = normal.dot(view_dir) <= 0
+        .filter(|(_b, _f, normal)| normal.dot(camera.dir) <= 0.0f32)
+        .filter_map(|(b, f, normal)| {
+            let half = 0.5 * (b.max - b.min);
+            // Next we need to find the hit point of the face plane (d (point on plane), n (normal)) and the ray (o (camera pos), rd (camera dir))
+            // The plane is given by x.dot(n) = d.dot(n) and the ray by x = o + t * rd
+            // plugging that into the plane equation yields a result for t = ((d - o) ∙ n) / (rd ∙ n) (where ∙ denotes the dot product)
+            let d = loc + b.center() + normal * half.dot(normal.abs());
+            let divisor = camera.dir.dot(normal);
+            // divisor == 0 indicates parallel dir -> eg. no collision or embedded (edge case does need to handle)
+            if divisor == 0.0f32 {
+                return None;
+            }
+            let t = (d - camera.pos).dot(normal) / divisor;
+
+            // We then obtain the hit point through x = o + t * rd
+            let x = camera.pos + t * camera.dir;
+
+            // We can then obtain the u/v coordinates of the point in the plane trough the parametric form of the plane equation
+            // x = d + u ∙ e0 + v ∙ e1 (where e0 and e1 are the edge vectors of the quad)
+            let e0 = f.orthogonal();
+            let e1 = e0.cross(normal).normalize();
+            // Since e0 ∙ e1 = 0 (eg. orthogonal) and e0 ∙ e0 = e1 ∙ e1 = 1
+            // we can compute u = (x - d) * e0 and v = (x - d) * e1
+            let u = (x - d).dot(e0);
+            let v = (x - d).dot(e1);
+            // Then the hit-point lies inside of the quad if u, v are within the half extents of the box
+            let (max_u, max_v) = (half.dot(e0.abs()), half.dot(e1.abs()));
+            (u.abs() <= max_u && v.abs() <= max_v).then_some((b, f, x))
+        })
+        .min_by(|(_, _, a), (_, _, b)| {
+            a.distance_squared(camera.pos)
+                .partial_cmp(&b.distance_squared(camera.pos))
+                .unwrap_or(Ordering::Equal)
+        })
+        .map(|(b, f, _)| (f.clone(), *b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slack_grows_with_the_mesh() {
+        // Room for a few more cubes at least, and a quarter more for large meshes
+        assert_eq!(Renderer::with_slack(0), 4 * 36);
+        assert_eq!(Renderer::with_slack(36), 5 * 36);
+        assert_eq!(Renderer::with_slack(4 * 4 * 36), 5 * 4 * 36);
+        assert_eq!(Renderer::with_slack(4000), 5000);
+    }
+
+    #[test]
+    fn capacity_only_grows() {
+        assert_eq!(Renderer::grown_capacity(100, 0), None);
+        assert_eq!(Renderer::grown_capacity(100, 100), None);
+        assert_eq!(
+            Renderer::grown_capacity(100, 101),
+            Some(Renderer::with_slack(101))
+        );
+        assert_eq!(
+            Renderer::grown_capacity(0, 1),
+            Some(Renderer::with_slack(1))
+        );
+    }
+}
@@ -0,0 +1,659 @@
+//! Post processing, eg. the scene is rendered into an offscreen (HDR if possible) target, which
+//! is then run through a chain of fullscreen passes onto the screen. Next to the color, the
+//! scene target keeps the depth and the normals of the opaque geometry (like a small G-buffer)
+//! for the screen space effects
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use anyhow::anyhow;
+use glow::{Context, Framebuffer, HasContext, Program, Texture, VertexArray};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+#[cfg(feature = "dev")]
+use super::ShaderSources;
+use super::{camera::Camera, preprocessor::Preprocessor, Handle, Renderer, Viewport};
+
+const POST_VERTEX_CODE: &str = include_str!("shaders/post.vert");
+
+/// The effects of the pass graph, that can be toggled at runtime
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostEffect {
+    Bloom,
+    ToneMapping,
+    /// The textures and the lighting are in gamma space already, so this is off by default
+    Gamma,
+    Fxaa,
+    Vignette,
+    /// Screen space ambient occlusion
+    Ssao,
+    /// Dark lines along depth and normal discontinuities
+    Outline,
+}
+
+impl PostEffect {
+    const ALL: [Self; 7] = [
+        Self::Bloom,
+        Self::ToneMapping,
+        Self::Gamma,
+        Self::Fxaa,
+        Self::Vignette,
+        Self::Ssao,
+        Self::Outline,
+    ];
+    const COUNT: usize = Self::ALL.len();
+
+    fn enabled_by_default(&self) -> bool {
+        matches!(self, PostEffect::ToneMapping | PostEffect::Fxaa)
+    }
+}
+
+/// The fragment shaders of the passes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PostShader {
+    Copy,
+    Bright,
+    Blur,
+    Bloom,
+    ToneMapping,
+    Gamma,
+    Fxaa,
+    Vignette,
+    Ssao,
+    AmbientOcclusion,
+    Outline,
+}
+
+impl PostShader {
+    const ALL: [Self; 11] = [
+        Self::Copy,
+        Self::Bright,
+        Self::Blur,
+        Self::Bloom,
+        Self::ToneMapping,
+        Self::Gamma,
+        Self::Fxaa,
+        Self::Vignette,
+        Self::Ssao,
+        Self::AmbientOcclusion,
+        Self::Outline,
+    ];
+
+    /// Name of the shader file in `shaders/`
+    #[cfg(feature = "dev")]
+    fn file(&self) -> &'static str {
+        match self {
+            PostShader::Copy => "copy.frag",
+            PostShader::Bright => "bright.frag",
+            PostShader::Blur => "blur.frag",
+            PostShader::Bloom => "bloom.frag",
+            PostShader::ToneMapping => "tonemap.frag",
+            PostShader::Gamma => "gamma.frag",
+            PostShader::Fxaa => "fxaa.frag",
+            PostShader::Vignette => "vignette.frag",
+            PostShader::Ssao => "ssao.frag",
+            PostShader::AmbientOcclusion => "ao.frag",
+            PostShader::Outline => "outline.frag",
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            PostShader::Copy => include_str!("shaders/copy.frag"),
+            PostShader::Bright => include_str!("shaders/bright.frag"),
+            PostShader::Blur => include_str!("shaders/blur.frag"),
+            PostShader::Bloom => include_str!("shaders/bloom.frag"),
+            PostShader::ToneMapping => include_str!("shaders/tonemap.frag"),
+            PostShader::Gamma => include_str!("shaders/gamma.frag"),
+            PostShader::Fxaa => include_str!("shaders/fxaa.frag"),
+            PostShader::Vignette => include_str!("shaders/vignette.frag"),
+            PostShader::Ssao => include_str!("shaders/ssao.frag"),
+            PostShader::AmbientOcclusion => include_str!("shaders/ao.frag"),
+            PostShader::Outline => include_str!("shaders/outline.frag"),
+        }
+    }
+}
+
+/// The images passes read from and write to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    /// The current image, writing to it renders into the next color target, or onto the
+    /// screen if it is the last pass that does so
+    Color,
+    /// Half resolution targets, eg. for the bloom and the ambient occlusion
+    Scratch(usize),
+    /// Depth of the scene (read only)
+    Depth,
+    /// World space normals of the scene, alpha is 0 where there is no opaque geometry (read only)
+    Normals,
+}
+
+struct Pass {
+    /// The pass only runs if its effect is enabled (or always, if it has none)
+    effect: Option<PostEffect>,
+    shader: PostShader,
+    /// Bound as `input0`, `input1`, ... and `texel_size` is the one of the first input
+    inputs: &'static [Slot],
+    output: Slot,
+    uniforms: &'static [(&'static str, [f32; 2])],
+}
+
+/// All passes in the order they are run in
+const GRAPH: [Pass; 13] = [
+    Pass {
+        effect: Some(PostEffect::Ssao),
+        shader: PostShader::Ssao,
+        inputs: &[Slot::Depth, Slot::Normals],
+        output: Slot::Scratch(0),
+        uniforms: &[],
+    },
+    Pass {
+        effect: Some(PostEffect::Ssao),
+        shader: PostShader::Blur,
+        inputs: &[Slot::Scratch(0)],
+        output: Slot::Scratch(1),
+        uniforms: &[("direction", [1.0, 0.0])],
+    },
+    Pass {
+        effect: Some(PostEffect::Ssao),
+        shader: PostShader::Blur,
+        inputs: &[Slot::Scratch(1)],
+        output: Slot::Scratch(0),
+        uniforms: &[("direction", [0.0, 1.0])],
+    },
+    Pass {
+        effect: Some(PostEffect::Ssao),
+        shader: PostShader::AmbientOcclusion,
+        inputs: &[Slot::Color, Slot::Scratch(0)],
+        output: Slot::Color,
+        uniforms: &[],
+    },
+    Pass {
+        effect: Some(PostEffect::Bloom),
+        shader: PostShader::Bright,
+        inputs: &[Slot::Color],
+        output: Slot::Scratch(0),
+        uniforms: &[],
+    },
+    Pass {
+        effect: Some(PostEffect::Bloom),
+        shader: PostShader::Blur,
+        inputs: &[Slot::Scratch(0)],
+        output: Slot::Scratch(1),
+        uniforms: &[("direction", [1.0, 0.0])],
+    },
+    Pass {
+        effect: Some(PostEffect::Bloom),
+        shader: PostShader::Blur,
+        inputs: &[Slot::Scratch(1)],
+        output: Slot::Scratch(0),
+        uniforms: &[("direction", [0.0, 1.0])],
+    },
+    Pass {
+        effect: Some(PostEffect::Bloom),
+        shader: PostShader::Bloom,
+        inputs: &[Slot::Color, Slot::Scratch(0)],
+        output: Slot::Color,
+        uniforms: &[],
+    },
+    Pass {
+        effect: Some(PostEffect::ToneMapping),
+        shader: PostShader::ToneMapping,
+        inputs: &[Slot::Color],
+        output: Slot::Color,
+        uniforms: &[],
+    },
+    Pass {
+        effect: Some(PostEffect::Gamma),
+        shader: PostShader::Gamma,
+        inputs: &[Slot::Color],
+        output: Slot::Color,
+        uniforms: &[],
+    },
+    Pass {
+        effect: Some(PostEffect::Outline),
+        shader: PostShader::Outline,
+        inputs: &[Slot::Color, Slot::Depth, Slot::Normals],
+        output: Slot::Color,
+        uniforms: &[],
+    },
+    Pass {
+        effect: Some(PostEffect::Fxaa),
+        shader: PostShader::Fxaa,
+        inputs: &[Slot::Color],
+        output: Slot::Color,
+        uniforms: &[],
+    },
+    Pass {
+        effect: Some(PostEffect::Vignette),
+        shader: PostShader::Vignette,
+        inputs: &[Slot::Color],
+        output: Slot::Color,
+        uniforms: &[],
+    },
+];
+
+/// Copies the current image onto the screen, if no enabled pass does so
+const COPY_PASS: Pass = Pass {
+    effect: None,
+    shader: PostShader::Copy,
+    inputs: &[Slot::Color],
+    output: Slot::Color,
+    uniforms: &[],
+};
+
+/// An offscreen color texture to render into
+struct RenderTarget {
+    framebuffer: Handle<Framebuffer>,
+    texture: Handle<Texture>,
+    /// Fraction of the viewport size
+    scale: u32,
+    size: Cell<(i32, i32)>,
+}
+
+impl RenderTarget {
+    unsafe fn new(
+        context: &Rc<Context>,
+        format: TargetFormat,
+        scale: u32,
+        viewport: &Viewport,
+    ) -> anyhow::Result<Self> {
+        let texture = create_texture(context, glow::LINEAR)?;
+        let framebuffer = context
+            .create_framebuffer()
+            .map_err(|e| anyhow!("failed to create render target framebuffer: {}", e))?;
+        let target = Self {
+            framebuffer: Handle::new(context, framebuffer, Context::delete_framebuffer),
+            texture,
+            scale,
+            size: Cell::new((0, 0)),
+        };
+        target.allocate(context, format, viewport);
+
+        context.bind_framebuffer(glow::FRAMEBUFFER, Some(framebuffer));
+        context.framebuffer_texture_2d(
+            glow::FRAMEBUFFER,
+            glow::COLOR_ATTACHMENT0,
+            glow::TEXTURE_2D,
+            Some(target.texture.raw()),
+            0,
+        );
+        context.bind_framebuffer(glow::FRAMEBUFFER, None);
+
+        Ok(target)
+    }
+
+    /// (Re)specifies the storage, eg. after the viewport changed
+    unsafe fn allocate(&self, context: &Context, format: TargetFormat, viewport: &Viewport) {
+        let width = (viewport.width / self.scale).max(1) as i32;
+        let height = (viewport.height / self.scale).max(1) as i32;
+        self.size.set((width, height));
+
+        let (internal_format, ty) = match format {
+            TargetFormat::Hdr => (glow::RGBA16F, glow::HALF_FLOAT),
+            TargetFormat::Ldr => (glow::RGBA8, glow::UNSIGNED_BYTE),
+        };
+        context.bind_texture(glow::TEXTURE_2D, Some(self.texture.raw()));
+        context.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            internal_format as _,
+            width,
+            height,
+            0,
+            glow::RGBA,
+            ty,
+            None,
+        );
+    }
+}
+
+/// The normals and the depth of the scene, attached to the scene target next to its color
+struct GBuffer {
+    normals: Handle<Texture>,
+    depth: Handle<Texture>,
+}
+
+impl GBuffer {
+    unsafe fn new(
+        context: &Rc<Context>,
+        scene: &RenderTarget,
+        viewport: &Viewport,
+    ) -> anyhow::Result<Self> {
+        let gbuffer = Self {
+            normals: create_texture(context, glow::NEAREST)?,
+            depth: create_texture(context, glow::NEAREST)?,
+        };
+        gbuffer.allocate(context, viewport);
+
+        context.bind_framebuffer(glow::FRAMEBUFFER, Some(scene.framebuffer.raw()));
+        context.framebuffer_texture_2d(
+            glow::FRAMEBUFFER,
+            glow::COLOR_ATTACHMENT1,
+            glow::TEXTURE_2D,
+            Some(gbuffer.normals.raw()),
+            0,
+        );
+        context.framebuffer_texture_2d(
+            glow::FRAMEBUFFER,
+            glow::DEPTH_ATTACHMENT,
+            glow::TEXTURE_2D,
+            Some(gbuffer.depth.raw()),
+            0,
+        );
+        context.bind_framebuffer(glow::FRAMEBUFFER, None);
+
+        Ok(gbuffer)
+    }
+
+    unsafe fn allocate(&self, context: &Context, viewport: &Viewport) {
+        let (width, height) = (viewport.width as i32, viewport.height as i32);
+        context.bind_texture(glow::TEXTURE_2D, Some(self.normals.raw()));
+        context.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGBA8 as _,
+            width,
+            height,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            None,
+        );
+
+        context.bind_texture(glow::TEXTURE_2D, Some(self.depth.raw()));
+        context.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::DEPTH_COMPONENT24 as _,
+            width,
+            height,
+            0,
+            glow::DEPTH_COMPONENT,
+            glow::UNSIGNED_INT,
+            None,
+        );
+    }
+}
+
+/// A texture without mipmaps, that is clamped at the edges
+unsafe fn create_texture(context: &Rc<Context>, filter: u32) -> anyhow::Result<Handle<Texture>> {
+    let texture = context
+        .create_texture()
+        .map_err(|e| anyhow!("failed to create render target texture: {}", e))?;
+    context.bind_texture(glow::TEXTURE_2D, Some(texture));
+    let texture = Handle::new(context, texture, Context::delete_texture);
+    for (param, value) in [
+        (glow::TEXTURE_MIN_FILTER, filter),
+        (glow::TEXTURE_MAG_FILTER, filter),
+        (glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE),
+        (glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE),
+    ] {
+        context.tex_parameter_i32(glow::TEXTURE_2D, param, value as _);
+    }
+    Ok(texture)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TargetFormat {
+    /// Half float, which keeps colors brighter than 1 for the tone mapping
+    Hdr,
+    /// Fallback, if rendering into float textures is not supported
+    Ldr,
+}
+
+pub struct PostProcessor {
+    format: TargetFormat,
+    /// The scene is rendered into this one (together with the `gbuffer`)
+    scene: RenderTarget,
+    gbuffer: GBuffer,
+    /// The passes ping pong between these, so that the scene target (whose depth and normals
+    /// might be read) is never written to by a pass
+    color: [RenderTarget; 2],
+    scratch: [RenderTarget; 2],
+    /// One program per `PostShader`
+    programs: Vec<RefCell<Handle<Program>>>,
+    // The passes draw a fullscreen triangle without any vertex data
+    vertex_array: Handle<VertexArray>,
+    enabled: Cell<[bool; PostEffect::COUNT]>,
+}
+
+impl PostProcessor {
+    pub(crate) unsafe fn new(
+        context: &Rc<Context>,
+        preprocessor: &Preprocessor,
+        viewport: &Viewport,
+    ) -> anyhow::Result<Self> {
+        let format = if context
+            .supported_extensions()
+            .contains("EXT_color_buffer_float")
+        {
+            TargetFormat::Hdr
+        } else {
+            TargetFormat::Ldr
+        };
+
+        let programs = PostShader::ALL
+            .iter()
+            .map(|shader| {
+                Renderer::compile_program(context, preprocessor, POST_VERTEX_CODE, shader.code())
+                    .map(RefCell::new)
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let mut enabled = [false; PostEffect::COUNT];
+        for effect in PostEffect::ALL.iter() {
+            enabled[*effect as usize] = effect.enabled_by_default();
+        }
+
+        let vertex_array = context
+            .create_vertex_array()
+            .map_err(|e| anyhow!("failed to create post processing vertex array: {}", e))?;
+        let vertex_array = Handle::new(context, vertex_array, Context::delete_vertex_array);
+
+        let scene = RenderTarget::new(context, format, 1, viewport)?;
+        let gbuffer = GBuffer::new(context, &scene, viewport)?;
+
+        Ok(Self {
+            format,
+            scene,
+            gbuffer,
+            color: [
+                RenderTarget::new(context, format, 1, viewport)?,
+                RenderTarget::new(context, format, 1, viewport)?,
+            ],
+            scratch: [
+                RenderTarget::new(context, format, 2, viewport)?,
+                RenderTarget::new(context, format, 2, viewport)?,
+            ],
+            programs,
+            vertex_array,
+            enabled: Cell::new(enabled),
+        })
+    }
+
+    pub(crate) unsafe fn resize(&self, context: &Context, viewport: &Viewport) {
+        let targets = std::iter::once(&self.scene)
+            .chain(self.color.iter())
+            .chain(self.scratch.iter());
+        for target in targets {
+            target.allocate(context, self.format, viewport);
+        }
+        self.gbuffer.allocate(context, viewport);
+    }
+
+    /// Names of the shader files of all passes, see `Renderer::reload_shaders`
+    #[cfg(feature = "dev")]
+    pub(crate) fn shader_files() -> impl Iterator<Item = &'static str> {
+        std::iter::once("post.vert").chain(PostShader::ALL.iter().map(PostShader::file))
+    }
+
+    /// Recompiles the passes from `sources`, those that fail keep their previous program
+    #[cfg(feature = "dev")]
+    pub(crate) unsafe fn reload_shaders(
+        &self,
+        context: &Rc<Context>,
+        preprocessor: &Preprocessor,
+        sources: &ShaderSources,
+        errors: &mut Vec<String>,
+    ) {
+        let vertex = sources
+            .get("post.vert")
+            .map_or(POST_VERTEX_CODE, String::as_str);
+        for shader in PostShader::ALL.iter() {
+            let fragment = sources
+                .get(shader.file())
+                .map_or(shader.code(), String::as_str);
+            match Renderer::compile_program(context, preprocessor, vertex, fragment) {
+                Ok(program) => *self.programs[*shader as usize].borrow_mut() = program,
+                Err(e) => errors.push(format!("post.vert + {}: {}", shader.file(), e)),
+            }
+        }
+    }
+
+    pub fn set_enabled(&self, effect: PostEffect, enabled: bool) {
+        let mut effects = self.enabled.get();
+        effects[effect as usize] = enabled;
+        self.enabled.set(effects);
+    }
+
+    pub fn is_enabled(&self, effect: PostEffect) -> bool {
+        self.enabled.get()[effect as usize]
+    }
+
+    /// Binds and clears the scene target, which leaves only the color to be written to
+    /// (eg. for the sky), see `write_normals`
+    pub(crate) unsafe fn begin_scene(&self, context: &Context, clear_color: glam::Vec3) {
+        context.bind_framebuffer(glow::FRAMEBUFFER, Some(self.scene.framebuffer.raw()));
+        context.draw_buffers(&[glow::COLOR_ATTACHMENT0, glow::COLOR_ATTACHMENT1]);
+
+        context.clear_color(clear_color.x, clear_color.y, clear_color.z, 1.0);
+        context.clear(glow::COLOR_BUFFER_BIT | glow::DEPTH_BUFFER_BIT);
+        context.clear_buffer_f32_slice(glow::COLOR, 1, &[0.0; 4]);
+
+        self.write_normals(context, false);
+    }
+
+    /// Whether the following draw calls also write their normals (the second output of the
+    /// solid shader), only opaque and cutout geometry should
+    pub(crate) unsafe fn write_normals(&self, context: &Context, write: bool) {
+        let normals = if write {
+            glow::COLOR_ATTACHMENT1
+        } else {
+            glow::NONE
+        };
+        context.draw_buffers(&[glow::COLOR_ATTACHMENT0, normals]);
+    }
+
+    /// Runs all enabled passes, which leaves the result in `output` (and `output` bound)
+    pub(crate) unsafe fn render(
+        &self,
+        context: &Context,
+        viewport: &Viewport,
+        camera: &Camera,
+        output: Option<Framebuffer>,
+    ) {
+        let mut passes: Vec<&Pass> = GRAPH
+            .iter()
+            .filter(|pass| {
+                pass.effect
+                    .into_iter()
+                    .all(|effect| self.is_enabled(effect))
+            })
+            .collect();
+        let last_color = match passes.iter().rposition(|pass| pass.output == Slot::Color) {
+            Some(last) => last,
+            None => {
+                passes.push(&COPY_PASS);
+                passes.len() - 1
+            }
+        };
+
+        context.disable(glow::DEPTH_TEST);
+        context.disable(glow::BLEND);
+        context.bind_vertex_array(Some(self.vertex_array.raw()));
+
+        let inverse_view_projection = camera.projection_view.inverse();
+
+        // The target holding the current image and the index of the next color target
+        let mut current = &self.scene;
+        let mut next = 0;
+        for (i, pass) in passes.iter().enumerate() {
+            let program = self.programs[pass.shader as usize].borrow().raw();
+            context.use_program(Some(program));
+
+            for (unit, slot) in pass.inputs.iter().enumerate() {
+                let (texture, (width, height)) = match slot {
+                    Slot::Color => (&current.texture, current.size.get()),
+                    Slot::Scratch(j) => (&self.scratch[*j].texture, self.scratch[*j].size.get()),
+                    Slot::Depth => (&self.gbuffer.depth, self.scene.size.get()),
+                    Slot::Normals => (&self.gbuffer.normals, self.scene.size.get()),
+                };
+                context.active_texture(glow::TEXTURE0 + unit as u32);
+                context.bind_texture(glow::TEXTURE_2D, Some(texture.raw()));
+                let loc = context.get_uniform_location(program, &format!("input{}", unit));
+                context.uniform_1_i32(loc.as_ref(), unit as i32);
+
+                if unit == 0 {
+                    let loc = context.get_uniform_location(program, "texel_size");
+                    context.uniform_2_f32(loc.as_ref(), 1.0 / width as f32, 1.0 / height as f32);
+                }
+            }
+            for (name, value) in pass.uniforms {
+                let loc = context.get_uniform_location(program, name);
+                context.uniform_2_f32_slice(loc.as_ref(), value);
+            }
+
+            // The screen space effects reconstruct positions from the depth
+            {
+                let loc = context.get_uniform_location(program, "view_projection");
+                context.uniform_matrix_4_f32_slice(
+                    loc.as_ref(),
+                    false,
+                    camera.projection_view.as_ref(),
+                );
+
+                let loc = context.get_uniform_location(program, "inverse_view_projection");
+                context.uniform_matrix_4_f32_slice(
+                    loc.as_ref(),
+                    false,
+                    inverse_view_projection.as_ref(),
+                );
+
+                let loc = context.get_uniform_location(program, "near_far");
+                context.uniform_2_f32(loc.as_ref(), Camera::NEAR, Camera::FAR);
+            }
+
+            let target = match pass.output {
+                Slot::Color if i == last_color => None,
+                Slot::Color => Some(&self.color[next]),
+                Slot::Scratch(j) => Some(&self.scratch[j]),
+                Slot::Depth | Slot::Normals => unreachable!("the scene is read only"),
+            };
+            match target {
+                Some(target) => {
+                    let (width, height) = target.size.get();
+                    context.bind_framebuffer(glow::FRAMEBUFFER, Some(target.framebuffer.raw()));
+                    context.viewport(0, 0, width, height);
+                }
+                None => {
+                    context.bind_framebuffer(glow::FRAMEBUFFER, output);
+                    context.viewport(0, 0, viewport.width as _, viewport.height as _);
+                }
+            }
+
+            context.draw_arrays(glow::TRIANGLES, 0, 3);
+
+            if pass.output == Slot::Color {
+                current = &self.color[next];
+                next = 1 - next;
+            }
+        }
+
+        context.active_texture(glow::TEXTURE0);
+        context.enable(glow::DEPTH_TEST);
+        context.enable(glow::BLEND);
+    }
+}
@@ -0,0 +1,233 @@
+//! A small GLSL preprocessor, eg. shared snippets are included with `#include "lighting.glsl"`
+//! and constants of the Rust side (like `CHUNK_SIZE`) are injected as `#define`s, so that the
+//! shaders don't have to keep copies of them
+use std::{collections::HashMap, fmt::Write};
+
+use anyhow::{anyhow, bail};
+
+use crate::{atlas::TextureBackend, world::chunk::CHUNK_SIZE};
+
+use super::{MAX_ANIMATIONS, MAX_FRAMES};
+
+/// The snippets in `shaders/`, that can be included
+pub(crate) const INCLUDES: [(&str, &str); 2] = [
+    ("lighting.glsl", include_str!("shaders/lighting.glsl")),
+    ("textures.glsl", include_str!("shaders/textures.glsl")),
+];
+
+/// Snippets may include other ones, but not that deep (eg. one that includes itself)
+const MAX_DEPTH: usize = 8;
+
+pub(crate) struct Preprocessor<'a> {
+    defines: Vec<(&'static str, String)>,
+    /// Used instead of the built in `INCLUDES`, eg. after they have been reloaded
+    overrides: Option<&'a HashMap<String, String>>,
+}
+
+impl<'a> Preprocessor<'a> {
+    /// Defines the constants that are shared with the shaders, eg. `CHUNK_SIZE`, and
+    /// `TEXTURE_ARRAY` if the block textures are sampled from a texture array
+    pub fn new(backend: TextureBackend) -> Self {
+        let mut defines = vec![
+            ("CHUNK_SIZE", CHUNK_SIZE.to_string()),
+            ("MAX_ANIMATIONS", MAX_ANIMATIONS.to_string()),
+            ("MAX_FRAMES", MAX_FRAMES.to_string()),
+        ];
+        if backend == TextureBackend::Array {
+            defines.push(("TEXTURE_ARRAY", String::new()));
+        }
+
+        Self {
+            defines,
+            overrides: None,
+        }
+    }
+
+    /// Includes the snippets in `sources` (by file name) instead of the built in ones
+    #[cfg(feature = "dev")]
+    pub fn with_overrides(mut self, sources: &'a HashMap<String, String>) -> Self {
+        self.overrides = Some(sources);
+        self
+    }
+
+    /// Puts the defines after the `#version` directive of `code` and expands its includes.
+    /// Compile errors keep the line numbers of the original files, where the source string
+    /// number of an error in a snippet is its index in `INCLUDES` + 1
+    pub fn process(&self, code: &str) -> anyhow::Result<String> {
+        let mut out = String::with_capacity(code.len());
+        let (body, first_line) = match code.split_once('\n') {
+            Some((version, body)) if version.trim_start().starts_with("#version") => {
+                out.push_str(version);
+                out.push('\n');
+                (body, 2)
+            }
+            _ => (code, 1),
+        };
+
+        for (name, value) in &self.defines {
+            writeln!(out, "#define {} {}", name, value)?;
+        }
+        self.expand(body, first_line, 0, 0, &mut out)?;
+        Ok(out)
+    }
+
+    fn expand(
+        &self,
+        code: &str,
+        first_line: usize,
+        source: usize,
+        depth: usize,
+        out: &mut String,
+    ) -> anyhow::Result<()> {
+        writeln!(out, "#line {} {}", first_line, source)?;
+        for (i, line) in code.lines().enumerate() {
+            let name = match parse_include(line)? {
+                Some(name) => name,
+                None => {
+                    out.push_str(line);
+                    out.push('\n');
+                    continue;
+                }
+            };
+
+            if depth >= MAX_DEPTH {
+                bail!("includes are nested too deep at \"{}\"", name);
+            }
+            let (index, snippet) = self.include(name)?;
+            self.expand(snippet, 1, index + 1, depth + 1, out)?;
+            writeln!(out, "#line {} {}", first_line + i + 1, source)?;
+        }
+        Ok(())
+    }
+
+    /// Index of the snippet `name` in `INCLUDES` and its code
+    fn include(&self, name: &str) -> anyhow::Result<(usize, &str)> {
+        let index = INCLUDES
+            .iter()
+            .position(|(file, _)| *file == name)
+            .ok_or_else(|| anyhow!("unknown include \"{}\"", name))?;
+        let code = self
+            .overrides
+            .and_then(|sources| sources.get(name))
+            .map_or(INCLUDES[index].1, String::as_str);
+        Ok((index, code))
+    }
+}
+
+/// The file name of an `#include "<name>"` line
+fn parse_include(line: &str) -> anyhow::Result<Option<&str>> {
+    let rest = match line.trim().strip_prefix("#include") {
+        Some(rest) => rest.trim(),
+        None => return Ok(None),
+    };
+    rest.strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .map(Some)
+        .ok_or_else(|| anyhow!("expected #include \"<name>\", got {}", line.trim()))
+}
+
+/// Defines `name` in `code`, eg. to compile a variant of a shader. Has to be done before the
+/// code is processed, since it is put right after the `#version` directive
+pub(crate) fn with_define(code: &str, name: &str) -> String {
+    match code.split_once('\n') {
+        // Line numbers continue as if it was not there
+        Some((version, rest)) => format!("{}\n#define {}\n#line 2\n{}", version, name, rest),
+        None => code.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocessor(overrides: Option<&HashMap<String, String>>) -> Preprocessor<'_> {
+        Preprocessor {
+            overrides,
+            ..Preprocessor::new(TextureBackend::Atlas)
+        }
+    }
+
+    /// Snippets that replace the built in ones, by file name
+    fn sources(snippets: &[(&str, &str)]) -> HashMap<String, String> {
+        snippets
+            .iter()
+            .map(|(name, code)| (name.to_string(), code.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn puts_defines_after_version() {
+        let out = preprocessor(None)
+            .process("#version 300 es\nvoid main() {}")
+            .unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "#version 300 es");
+        assert_eq!(lines[1], format!("#define CHUNK_SIZE {}", CHUNK_SIZE));
+        assert!(!out.contains("TEXTURE_ARRAY"));
+        // The body keeps its line number
+        assert_eq!(lines[lines.len() - 2..], ["#line 2 0", "void main() {}"]);
+    }
+
+    #[test]
+    fn expands_nested_includes() {
+        let sources = sources(&[
+            (
+                "lighting.glsl",
+                "float a;\n#include \"textures.glsl\"\nfloat b;",
+            ),
+            ("textures.glsl", "float c;"),
+        ]);
+        let out = preprocessor(Some(&sources))
+            .process("#version 300 es\n  #include \"lighting.glsl\"\nvoid main() {}")
+            .unwrap();
+        let body: Vec<_> = out.lines().skip_while(|l| l.starts_with('#')).collect();
+        assert_eq!(
+            body,
+            [
+                "float a;",
+                "#line 1 2",
+                "float c;",
+                "#line 3 1",
+                "float b;",
+                "#line 3 0",
+                "void main() {}",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_include() {
+        let error = preprocessor(None)
+            .process("#version 300 es\n#include \"missing.glsl\"")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "unknown include \"missing.glsl\"");
+    }
+
+    #[test]
+    fn rejects_malformed_include() {
+        for line in [
+            "#include lighting.glsl",
+            "#include \"lighting.glsl",
+            "#include",
+        ] {
+            let error = preprocessor(None).process(line).unwrap_err();
+            assert!(
+                error.to_string().starts_with("expected #include"),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn stops_at_self_include() {
+        let sources = sources(&[("lighting.glsl", "#include \"lighting.glsl\"")]);
+        let error = preprocessor(Some(&sources))
+            .process("#include \"lighting.glsl\"")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "includes are nested too deep at \"lighting.glsl\""
+        );
+    }
+}
@@ -0,0 +1,59 @@
+//! Resource packs, eg. alternate block textures that can be swapped in at runtime
+use anyhow::bail;
+use enum_iterator::IntoEnumIterator;
+
+use crate::atlas::{
+    builder::{AtlasLayout, Image},
+    BlockTexture, TextureBackend,
+};
+
+use super::Renderer;
+
+/// A loaded atlas image together with its manifest, that maps every `BlockTexture` name to
+/// its tile in the atlas (in the format of `AtlasLayout::to_table`)
+pub struct ResourcePack {
+    image: Image,
+    layout: AtlasLayout,
+}
+
+impl ResourcePack {
+    pub async fn load(
+        renderer: &Renderer,
+        atlas_src: &str,
+        manifest: &str,
+    ) -> anyhow::Result<Self> {
+        let layout = AtlasLayout::from_table(manifest)?;
+        if let Some(texture) =
+            BlockTexture::into_enum_iter().find(|t| layout.tile(&t.name()).is_none())
+        {
+            bail!("resource pack has no tile for {:?}", texture);
+        }
+
+        let image = renderer.images.load(atlas_src).await?;
+        if (image.width, image.height) != (layout.width, layout.height) {
+            bail!(
+                "atlas {} is {}x{}, but the manifest expects {}x{}",
+                atlas_src,
+                image.width,
+                image.height,
+                layout.width,
+                layout.height
+            );
+        }
+
+        Ok(Self { image, layout })
+    }
+}
+
+impl Renderer {
+    /// Replaces the block textures by the ones of `pack`, returns whether the texture
+    /// coordinates changed, in which case all meshes have to be rebuilt
+    pub fn use_resource_pack(&self, pack: ResourcePack) -> anyhow::Result<bool> {
+        let ResourcePack { image, layout } = pack;
+
+        let previous = self.texture_layout();
+        self.use_atlas(&image, &layout)?;
+        // The vertices of a texture array only refer to the layers, which stay the same
+        Ok(previous.backend() == TextureBackend::Atlas && *self.texture_layout() != *previous)
+    }
+}
@@ -0,0 +1,15 @@
+#version 300 es
+
+precision mediump float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+// the scene and the blurred ambient occlusion
+uniform sampler2D input0;
+uniform sampler2D input1;
+
+void main() {
+    vec3 color = texture(input0, pass_uv).rgb * texture(input1, pass_uv).r;
+    out_color = vec4(color, 1.0);
+}
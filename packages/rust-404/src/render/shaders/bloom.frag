@@ -0,0 +1,17 @@
+#version 300 es
+
+precision mediump float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+// the scene and the blurred bright parts of it
+uniform sampler2D input0;
+uniform sampler2D input1;
+
+const float INTENSITY = 0.6;
+
+void main() {
+    vec3 color = texture(input0, pass_uv).rgb + INTENSITY * texture(input1, pass_uv).rgb;
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,25 @@
+#version 300 es
+
+precision mediump float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+uniform sampler2D input0;
+uniform vec2 texel_size;
+// (1, 0) for the horizontal and (0, 1) for the vertical pass of the separable blur
+uniform vec2 direction;
+
+// 9 tap gaussian, folded into 5 linearly filtered samples
+const float OFFSETS[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float WEIGHTS[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+    vec2 stride = direction * texel_size;
+    vec3 color = texture(input0, pass_uv).rgb * WEIGHTS[0];
+    for(int i = 1; i < 3; i++) {
+        color += texture(input0, pass_uv + OFFSETS[i] * stride).rgb * WEIGHTS[i];
+        color += texture(input0, pass_uv - OFFSETS[i] * stride).rgb * WEIGHTS[i];
+    }
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 300 es
+
+precision mediump float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+uniform sampler2D input0;
+
+// everything brighter than this starts to glow
+const float THRESHOLD = 0.8;
+// width of the transition around the threshold
+const float KNEE = 0.2;
+
+void main() {
+    vec3 color = texture(input0, pass_uv).rgb;
+    float brightness = max(color.r, max(color.g, color.b));
+    float weight = smoothstep(THRESHOLD - KNEE, THRESHOLD + KNEE, brightness);
+    out_color = vec4(color * weight, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 300 es
+
+precision mediump float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+uniform sampler2D input0;
+
+void main() {
+    out_color = vec4(texture(input0, pass_uv).rgb, 1.0);
+}
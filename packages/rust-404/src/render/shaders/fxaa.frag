@@ -0,0 +1,46 @@
+#version 300 es
+
+precision mediump float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+uniform sampler2D input0;
+uniform vec2 texel_size;
+
+const float REDUCE_MIN = 1.0 / 128.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float SPAN_MAX = 8.0;
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+// The simple FXAA variant (without the search along the edge), which blurs along the
+// direction of the edge that is estimated from the luma of the corners
+void main() {
+    vec3 rgb_m = texture(input0, pass_uv).rgb;
+    float luma_nw = luma(texture(input0, pass_uv + vec2(-1.0, -1.0) * texel_size).rgb);
+    float luma_ne = luma(texture(input0, pass_uv + vec2(1.0, -1.0) * texel_size).rgb);
+    float luma_sw = luma(texture(input0, pass_uv + vec2(-1.0, 1.0) * texel_size).rgb);
+    float luma_se = luma(texture(input0, pass_uv + vec2(1.0, 1.0) * texel_size).rgb);
+    float luma_m = luma(rgb_m);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)), (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel_size;
+
+    vec3 rgb_a = 0.5 * (texture(input0, pass_uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        texture(input0, pass_uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (texture(input0, pass_uv - dir * 0.5).rgb +
+        texture(input0, pass_uv + dir * 0.5).rgb);
+
+    // the wider blur is only used, if it did not pick up colors from outside of the local range
+    float luma_b = luma(rgb_b);
+    vec3 color = (luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b;
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,15 @@
+#version 300 es
+
+precision mediump float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+uniform sampler2D input0;
+
+const float GAMMA = 2.2;
+
+void main() {
+    vec3 color = texture(input0, pass_uv).rgb;
+    out_color = vec4(pow(max(color, vec3(0.0)), vec3(1.0 / GAMMA)), 1.0);
+}
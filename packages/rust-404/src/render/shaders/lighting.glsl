@@ -0,0 +1,36 @@
+// Lighting and fog of the world, eg. by the sun (or the moon)
+uniform vec3 light_dir;
+uniform vec3 view_pos;
+uniform vec3 light_color;
+uniform vec3 ambient_color;
+uniform vec3 fog_color;
+uniform float fog_start;
+uniform float fog_end;
+
+// Ambient, diffuse and specular light on a surface of `color` at `frag_pos` (in world space)
+vec3 lighting(vec3 color, vec3 norm, vec3 frag_pos) {
+    // ambient
+    vec3 ambient = ambient_color;
+
+    // diffuse 
+    float diffuse_strength = 0.6;
+    float diff = max(dot(norm, light_dir), 0.0);
+    vec3 diffuse = diffuse_strength * diff * light_color;
+
+    // specular
+    float specular_strength = 0.3;
+    vec3 view_dir = normalize(view_pos - frag_pos);
+    vec3 reflect_dir = reflect(-light_dir, norm);
+    float spec = pow(max(dot(view_dir, reflect_dir), 0.0), 2.0);
+    vec3 specular = specular_strength * spec * light_color;
+
+    return (ambient + diffuse + specular) * color;
+}
+
+// Fog, exponential from fog_start on, so that it is (almost) opaque at fog_end
+vec3 fog(vec3 color, vec3 frag_pos) {
+    float dist = length(view_pos - frag_pos);
+    float fog_density = 4.0 / max(fog_end - fog_start, 0.001);
+    float amount = 1.0 - exp(-fog_density * max(dist - fog_start, 0.0));
+    return mix(color, fog_color, amount);
+}
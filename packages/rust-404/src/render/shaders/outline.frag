@@ -0,0 +1,53 @@
+#version 300 es
+
+precision highp float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+// the scene, its depth and its normals
+uniform sampler2D input0;
+uniform sampler2D input1;
+uniform sampler2D input2;
+uniform vec2 texel_size;
+uniform vec2 near_far;
+
+const vec3 OUTLINE_COLOR = vec3(0.05, 0.05, 0.08);
+// relative depth difference, that counts as an edge
+const float DEPTH_THRESHOLD = 0.08;
+// 1 - cos of the angle between the normals, that counts as an edge
+const float NORMAL_THRESHOLD = 0.4;
+
+float linear_depth(float depth) {
+    float near = near_far.x;
+    float far = near_far.y;
+    float z = depth * 2.0 - 1.0;
+    return 2.0 * near * far / (far + near - z * (far - near));
+}
+
+// How strongly the neighbour at `offset` differs from the center
+float edge(vec2 offset, float depth, vec4 normal) {
+    vec2 uv = pass_uv + offset * texel_size;
+    float d = linear_depth(texture(input1, uv).r);
+    vec4 n = texture(input2, uv);
+
+    // only neighbours behind the center count, so the line is on the closer side of the edge
+    float depth_edge = step(DEPTH_THRESHOLD, (d - depth) / depth);
+    // both have to be geometry for the normals to be comparable
+    float normal_edge = normal.a * n.a *
+        step(NORMAL_THRESHOLD, 1.0 - dot(normal.xyz * 2.0 - 1.0, n.xyz * 2.0 - 1.0));
+    return max(depth_edge, normal_edge);
+}
+
+void main() {
+    vec3 color = texture(input0, pass_uv).rgb;
+    float depth = linear_depth(texture(input1, pass_uv).r);
+    vec4 normal = texture(input2, pass_uv);
+
+    float outline = max(max(edge(vec2(1.0, 0.0), depth, normal), edge(vec2(-1.0, 0.0), depth, normal)),
+        max(edge(vec2(0.0, 1.0), depth, normal), edge(vec2(0.0, -1.0), depth, normal)));
+    // no lines on the sky
+    outline *= normal.a;
+
+    out_color = vec4(mix(color, OUTLINE_COLOR, outline), 1.0);
+}
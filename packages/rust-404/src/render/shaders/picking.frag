@@ -1,10 +1,29 @@
 #version 300 es
 
 precision mediump float;
-out vec4 out_color;
+layout(location = 0) out vec4 out_color;
+// the id attachment is an R32UI texture
+layout(location = 1) out highp uint out_id;
 
 in vec3 pass_color;
+in vec3 pass_tex;
+
+#include "textures.glsl"
+// cutout geometry can be picked only where it is actually visible
+uniform float alpha_cutoff;
+
+// index of the mesh (+ 1), so that the hit chunk can be identified
+uniform highp uint mesh_id;
 
 void main() {
-    out_color = vec4(pass_color, 1.0);
+#ifdef TEXTURE_ARRAY
+    float alpha = texture(uSampler, pass_tex).a;
+#else
+    float alpha = texture(uSampler, pass_tex.xy).a;
+#endif
+    if(alpha < alpha_cutoff)
+        discard;
+
+    out_color = vec4(pass_color, 1.0f);
+    out_id = mesh_id;
 }
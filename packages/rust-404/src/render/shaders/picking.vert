@@ -1,15 +1,18 @@
 #version 300 es
 
-#define CHUNK_SIZE 16.0
-
 in vec3 position;
+layout(location = 2) in vec3 tex_coord;
 layout(location = 3) in vec3 base_loc;
 
 uniform mat4 view_projection;
+uniform mat4 model;
 
 out vec3 pass_color;
+out vec3 pass_tex;
 
 void main() {
-    pass_color = (1.0f / (CHUNK_SIZE - 1.0)) * base_loc;
-    gl_Position = view_projection * vec4(position.xyz, 1.0f);
+    // CHUNK_SIZE is defined by the renderer
+    pass_color = (1.0f / float(CHUNK_SIZE - 1)) * base_loc;
+    pass_tex = tex_coord;
+    gl_Position = view_projection * model * vec4(position.xyz, 1.0f);
 }
\ No newline at end of file
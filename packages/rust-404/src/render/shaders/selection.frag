@@ -0,0 +1,21 @@
+#version 300 es
+
+precision mediump float;
+layout(location = 0) out vec4 out_color;
+layout(location = 1) out vec4 out_normal;
+
+uniform vec4 glow_color;
+// total elapsed time in seconds, see solid.frag
+uniform highp float time;
+
+in vec3 pass_normal;
+flat in vec4 pass_tint;
+
+void main() {
+    // unlit, but pulses brighter than the color (which the bloom picks up)
+    float pulse = 0.5 + 0.5 * sin(4.0 * time);
+    vec3 color = glow_color.rgb * pass_tint.rgb * (1.0 + 0.6 * pulse);
+
+    out_color = vec4(color, 1.0);
+    out_normal = vec4(normalize(pass_normal) * 0.5 + 0.5, 1.0);
+}
@@ -0,0 +1,43 @@
+#version 300 es
+
+precision highp float;
+out vec4 out_color;
+
+uniform mat4 inv_view_projection;
+uniform vec3 view_pos;
+uniform vec3 sun_dir;
+uniform vec3 zenith_color;
+uniform vec3 horizon_color;
+uniform vec3 sun_color;
+
+in vec2 pass_ndc;
+
+// cos of the angular radius of the discs
+const float SUN_SIZE = 0.9985;
+const float MOON_SIZE = 0.999;
+const vec3 moon_color = vec3(0.85, 0.88, 0.95);
+
+void main() {
+    // Reconstruct the view ray for this fragment
+    vec4 world = inv_view_projection * vec4(pass_ndc, 1.0, 1.0);
+    vec3 dir = normalize(world.xyz / world.w - view_pos);
+
+    // gradient
+    float t = pow(1.0 - max(dir.y, 0.0), 4.0);
+    vec3 color = mix(zenith_color, horizon_color, t);
+
+    // sun and moon are hidden below the horizon
+    float above = smoothstep(-0.02, 0.0, dir.y);
+
+    // sun (with a bit of a glow around it)
+    float sun_cos = dot(dir, sun_dir);
+    float sun = smoothstep(SUN_SIZE - 0.0004, SUN_SIZE, sun_cos);
+    color += 0.25 * sun_color * pow(max(sun_cos, 0.0), 64.0) * above;
+    color = mix(color, sun_color, sun * above);
+
+    // moon
+    float moon = smoothstep(MOON_SIZE - 0.0004, MOON_SIZE, dot(dir, -sun_dir));
+    color = mix(color, moon_color, moon * above);
+
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,10 @@
+#version 300 es
+
+out vec2 pass_ndc;
+
+void main() {
+    // Fullscreen triangle without any vertex data, eg. (-1, -1), (3, -1), (-1, 3)
+    vec2 pos = vec2(float((gl_VertexID & 1) << 2) - 1.0, float((gl_VertexID & 2) << 1) - 1.0);
+    pass_ndc = pos;
+    gl_Position = vec4(pos, 0.0, 1.0);
+}
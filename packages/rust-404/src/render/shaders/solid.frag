@@ -1,45 +1,103 @@
 #version 300 es
 
 precision mediump float;
-out vec4 out_color;
+layout(location = 0) out vec4 out_color;
+// world space normal (scaled into 0..1), for the screen space post processing
+layout(location = 1) out vec4 out_normal;
 
-uniform sampler2D uSampler;
-uniform vec3 light_dir;
-uniform vec3 view_pos;
+// MAX_ANIMATIONS and MAX_FRAMES are defined by the renderer
+#include "textures.glsl"
+#include "lighting.glsl"
+
+#ifdef SOLID_COLOR
+// used instead of the block textures
 uniform vec4 solid_color;
+#endif
+// texels with less alpha are discarded
+uniform float alpha_cutoff;
+// whether the output should be blended with what is behind it
+uniform bool translucent;
+// total elapsed time in seconds, drives the texture animations. mediump would run out of
+// precision after a few minutes, which makes the animations stutter
+uniform highp float time;
+uniform int animation_count;
+// atlas rect (base, extend) or texture array layer of each animated texture
+uniform vec4 animation_rects[MAX_ANIMATIONS];
+uniform int animation_layers[MAX_ANIMATIONS];
+// offset (in tile space) of each frame and the time at which it ends, relative to the start
+uniform vec3 animation_frames[MAX_ANIMATIONS * MAX_FRAMES];
+uniform int animation_frame_counts[MAX_ANIMATIONS];
 
 in vec3 pass_normal;
-in vec2 pass_tex;
+in vec3 pass_tex;
 in vec3 pass_frag_pos;
+flat in vec4 pass_tint;
 
-const vec3 light_color = vec3(1.0);
+// Offset of the current frame of the i-th animation
+vec2 animation_offset(int i) {
+    int count = animation_frame_counts[i];
+    highp float t = mod(time, animation_frames[i * MAX_FRAMES + count - 1].z);
+    vec2 offset = vec2(0.0);
+    for(int f = 0; f < MAX_FRAMES; f++) {
+        if(f >= count)
+            break;
+        vec3 frame = animation_frames[i * MAX_FRAMES + f];
+        offset = frame.xy;
+        if(t < frame.z)
+            break;
+    }
+    return offset;
+}
 
-void main() {
-    vec3 color;
-    if(solid_color.a > 0.01)
-        color = solid_color.xyz;
-    else
-        color = vec3(texture(uSampler, pass_tex.xy));
+#ifdef TEXTURE_ARRAY
+// Scrolls the texture coordinate, if it lies in an animated layer (the texture repeats anyway)
+vec3 animate(vec3 tex) {
+    for(int i = 0; i < MAX_ANIMATIONS; i++) {
+        if(i >= animation_count)
+            break;
+        if(int(tex.z + 0.5) == animation_layers[i])
+            return vec3(tex.xy + animation_offset(i), tex.z);
+    }
+    return tex;
+}
+#else
+// Scrolls the texture coordinate inside of its tile, if it lies in an animated tile
+vec2 animate(vec2 tex) {
+    for(int i = 0; i < MAX_ANIMATIONS; i++) {
+        if(i >= animation_count)
+            break;
 
-    // vec3 light_dir = normalize(light_pos - pass_frag_pos);
+        vec4 rect = animation_rects[i];
+        vec2 local = (tex - rect.xy) / rect.zw;
+        if(any(lessThan(local, vec2(0.0))) || any(greaterThan(local, vec2(1.0))))
+            continue;
 
-    // ambient
-    float ambient_strength = 0.4;
-    vec3 ambient = ambient_strength * light_color;
+        return rect.xy + fract(local + animation_offset(i)) * rect.zw;
+    }
+    return tex;
+}
+#endif
+
+void main() {
+    vec4 color;
+#if defined(SOLID_COLOR)
+    color = solid_color;
+#elif defined(TEXTURE_ARRAY)
+    color = texture(uSampler, animate(pass_tex));
+#else
+    // the explicit gradients avoid seams where the animated coordinates wrap around
+    color = textureGrad(uSampler, animate(pass_tex.xy), dFdx(pass_tex.xy), dFdy(pass_tex.xy));
+#endif
+    color *= pass_tint;
+
+    if(color.a < alpha_cutoff)
+        discard;
 
-    // diffuse 
-    float diffuse_strength = 0.6;
     vec3 norm = normalize(pass_normal);
-    float diff = max(dot(norm, light_dir), 0.0);
-    vec3 diffuse = diffuse_strength * diff * light_color;
-
-    // specular
-    float specular_strength = 0.3;
-    vec3 view_dir = normalize(view_pos - pass_frag_pos);
-    vec3 reflect_dir = reflect(-light_dir, norm);
-    float spec = pow(max(dot(view_dir, reflect_dir), 0.0), 2.0);
-    vec3 specular = specular_strength * spec * light_color;
-
-    vec3 result = (ambient + diffuse + specular) * color;
-    out_color = vec4(result, 1.0);
+    vec3 result = fog(lighting(color.rgb, norm, pass_frag_pos), pass_frag_pos);
+
+    // blending expects premultiplied alpha
+    float alpha = translucent ? color.a : 1.0;
+    out_color = vec4(result * alpha, alpha);
+    out_normal = vec4(norm * 0.5 + 0.5, 1.0);
 }
@@ -2,19 +2,29 @@
 
 uniform mat4 view_projection;
 uniform mat4 model;
+// the model matrix and the tint come from the per instance attributes instead
+uniform bool instanced;
 
 in vec3 position;
 in vec3 norm;
-in vec2 tex_coord;
+// (u, v, layer), the layer is only used with TEXTURE_ARRAY
+in vec3 tex_coord;
+// NOTE: These have to be kept in sync with `Instance` in render/mod.rs
+layout(location = 4) in mat4 instance_model;
+layout(location = 8) in vec4 instance_color;
 
 out vec3 pass_normal;
-out vec2 pass_tex;
+out vec3 pass_tex;
 out vec3 pass_frag_pos;
+flat out vec4 pass_tint;
 
 void main() {
-    gl_Position = view_projection * model * vec4(position.xyz, 1.0);
+    mat4 transform = instanced ? instance_model : model;
+    pass_tint = instanced ? instance_color : vec4(1.0);
 
-    pass_normal = mat3(transpose(inverse(model))) * norm;
+    gl_Position = view_projection * transform * vec4(position.xyz, 1.0);
+
+    pass_normal = mat3(transpose(inverse(transform))) * norm;
     pass_tex = tex_coord;
-    pass_frag_pos = vec3(model * vec4(position.xyz, 1.0));
+    pass_frag_pos = vec3(transform * vec4(position.xyz, 1.0));
 }
\ No newline at end of file
@@ -0,0 +1,81 @@
+#version 300 es
+
+precision highp float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+// depth and normals of the scene
+uniform sampler2D input0;
+uniform sampler2D input1;
+uniform mat4 view_projection;
+uniform mat4 inverse_view_projection;
+uniform vec2 near_far;
+
+const int SAMPLES = 16;
+const float RADIUS = 0.6;
+const float BIAS = 0.03;
+const float STRENGTH = 1.2;
+
+// view space distance of a value in the depth buffer
+float linear_depth(float depth) {
+    float near = near_far.x;
+    float far = near_far.y;
+    float z = depth * 2.0 - 1.0;
+    return 2.0 * near * far / (far + near - z * (far - near));
+}
+
+vec3 world_pos(vec2 uv, float depth) {
+    vec4 pos = inverse_view_projection * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+    return pos.xyz / pos.w;
+}
+
+float hash(vec2 p) {
+    return fract(sin(dot(p, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+// The i-th point of the kernel inside of the (z up) unit hemisphere, more of them close to
+// the center
+vec3 kernel(int i) {
+    float fi = float(i);
+    float phi = fi * 2.399963;
+    float z = fract(fi * 0.618034) * 0.9 + 0.1;
+    float r = sqrt(1.0 - z * z);
+    float scale = (fi + 1.0) / float(SAMPLES);
+    return vec3(r * cos(phi), r * sin(phi), z) * mix(0.1, 1.0, scale * scale);
+}
+
+void main() {
+    vec4 normal = texture(input1, pass_uv);
+    float depth = texture(input0, pass_uv).r;
+    // nothing (eg. the sky or translucent geometry) has been written there
+    if(normal.a < 0.5 || depth >= 1.0) {
+        out_color = vec4(1.0);
+        return;
+    }
+
+    vec3 pos = world_pos(pass_uv, depth);
+    vec3 n = normalize(normal.xyz * 2.0 - 1.0);
+
+    // the kernel is randomly rotated around the normal per pixel, the blur evens that out
+    float angle = 6.283185 * hash(gl_FragCoord.xy);
+    vec3 random = vec3(cos(angle), sin(angle), hash(gl_FragCoord.yx));
+    vec3 tangent = normalize(random - n * dot(random, n));
+    mat3 tbn = mat3(tangent, cross(n, tangent), n);
+
+    float center = linear_depth(depth);
+    float occlusion = 0.0;
+    for(int i = 0; i < SAMPLES; i++) {
+        vec3 sample_pos = pos + tbn * kernel(i) * RADIUS;
+        vec4 clip = view_projection * vec4(sample_pos, 1.0);
+        vec2 uv = clip.xy / clip.w * 0.5 + 0.5;
+
+        // w of the clip position is the view space distance of the sample
+        float scene = linear_depth(texture(input0, uv).r);
+        float range = smoothstep(0.0, 1.0, RADIUS / abs(center - scene));
+        occlusion += (scene < clip.w - BIAS ? 1.0 : 0.0) * range;
+    }
+
+    float ao = clamp(1.0 - STRENGTH * occlusion / float(SAMPLES), 0.0, 1.0);
+    out_color = vec4(vec3(ao), 1.0);
+}
@@ -0,0 +1,6 @@
+// block textures, see TextureBackend
+#ifdef TEXTURE_ARRAY
+uniform mediump sampler2DArray uSampler;
+#else
+uniform sampler2D uSampler;
+#endif
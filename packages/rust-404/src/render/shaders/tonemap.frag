@@ -0,0 +1,25 @@
+#version 300 es
+
+precision mediump float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+uniform sampler2D input0;
+
+const float EXPOSURE = 1.0;
+
+// Curve fit of the ACES filmic tone mapping (by Krzysztof Narkowicz)
+vec3 aces(vec3 x) {
+    const float a = 2.51;
+    const float b = 0.03;
+    const float c = 2.43;
+    const float d = 0.59;
+    const float e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
+}
+
+void main() {
+    vec3 color = texture(input0, pass_uv).rgb;
+    out_color = vec4(aces(EXPOSURE * color), 1.0);
+}
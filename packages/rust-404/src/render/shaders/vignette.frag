@@ -0,0 +1,18 @@
+#version 300 es
+
+precision mediump float;
+out vec4 out_color;
+
+in vec2 pass_uv;
+
+uniform sampler2D input0;
+
+const float STRENGTH = 0.35;
+
+void main() {
+    vec3 color = texture(input0, pass_uv).rgb;
+    // 0 in the center and 1 in the corners
+    vec2 offset = pass_uv - 0.5;
+    float dist = 2.0 * dot(offset, offset);
+    out_color = vec4(color * (1.0 - STRENGTH * smoothstep(0.0, 1.0, dist)), 1.0);
+}
@@ -0,0 +1,142 @@
+//! Time of day handling, eg. where the sun is and which colors the sky and the light have
+use std::f32::consts::TAU;
+
+/// A color keyframe, keyed by the height of the sun (eg. `sun_dir.y` in [-1; 1])
+type Keyframe = (f32, glam::Vec3);
+
+const ZENITH: [Keyframe; 4] = [
+    (-0.3, glam::const_vec3!([0.01, 0.015, 0.05])),
+    (0.0, glam::const_vec3!([0.2, 0.25, 0.45])),
+    (0.25, glam::const_vec3!([0.25, 0.5, 0.9])),
+    (1.0, glam::const_vec3!([0.2, 0.45, 0.9])),
+];
+
+const HORIZON: [Keyframe; 4] = [
+    (-0.3, glam::const_vec3!([0.03, 0.04, 0.1])),
+    (0.0, glam::const_vec3!([0.95, 0.5, 0.3])),
+    (0.25, glam::const_vec3!([0.7, 0.85, 1.0])),
+    (1.0, glam::const_vec3!([0.75, 0.88, 1.0])),
+];
+
+const SUN_LIGHT: [Keyframe; 4] = [
+    (-0.05, glam::const_vec3!([0.0, 0.0, 0.0])),
+    (0.05, glam::const_vec3!([0.6, 0.3, 0.15])),
+    (0.3, glam::const_vec3!([1.0, 0.95, 0.85])),
+    (1.0, glam::const_vec3!([1.0, 1.0, 1.0])),
+];
+
+const SUN_DISC: [Keyframe; 3] = [
+    (0.0, glam::const_vec3!([1.0, 0.45, 0.15])),
+    (0.3, glam::const_vec3!([1.0, 0.95, 0.8])),
+    (1.0, glam::const_vec3!([1.0, 1.0, 0.95])),
+];
+
+const AMBIENT: [Keyframe; 3] = [
+    (-0.2, glam::const_vec3!([0.06, 0.07, 0.12])),
+    (0.05, glam::const_vec3!([0.25, 0.2, 0.2])),
+    (0.3, glam::const_vec3!([0.4, 0.4, 0.4])),
+];
+
+const MOON_LIGHT: glam::Vec3 = glam::const_vec3!([0.15, 0.18, 0.3]);
+
+/// Linear interpolation between the keyframes (which have to be sorted by key)
+fn sample(keyframes: &[Keyframe], t: f32) -> glam::Vec3 {
+    let (first, last) = (keyframes[0], keyframes[keyframes.len() - 1]);
+    if t <= first.0 {
+        return first.1;
+    }
+    keyframes
+        .windows(2)
+        .find(|w| t <= w[1].0)
+        .map(|w| {
+            let ((a, from), (b, to)) = (w[0], w[1]);
+            from.lerp(to, (t - a) / (b - a))
+        })
+        .unwrap_or(last.1)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+pub struct Sky {
+    /// The time of day in [0; 1), where 0 is midnight, 0.25 sunrise, 0.5 noon and 0.75 sunset
+    time: f32,
+}
+
+impl Sky {
+    /// Length of a full day in seconds
+    const DAY_LENGTH: f32 = 240.0;
+    /// We start in the morning, so that the first thing the user sees is not a black screen
+    const START_TIME: f32 = 0.3;
+    /// Tilt of the sun path, so that the sun is not directly overhead at noon
+    const TILT: f32 = 0.4;
+
+    pub fn new() -> Self {
+        Self {
+            time: Self::START_TIME,
+        }
+    }
+
+    /// Advance the time of day, `total` is the total elapsed time in seconds
+    pub fn update(&mut self, total: f32) {
+        self.time = (Self::START_TIME + total / Self::DAY_LENGTH).fract();
+    }
+
+    pub fn sun_dir(&self) -> glam::Vec3 {
+        let angle = self.time * TAU;
+        glam::Mat3::from_rotation_x(Self::TILT) * glam::vec3(angle.sin(), -angle.cos(), 0.0)
+    }
+
+    pub fn moon_dir(&self) -> glam::Vec3 {
+        -self.sun_dir()
+    }
+
+    fn sun_height(&self) -> f32 {
+        self.sun_dir().y
+    }
+
+    /// The direction towards the currently active light source, this is never below the horizon,
+    /// since we switch from sun to moon when both of them are (and their light is zero)
+    pub fn light_dir(&self) -> glam::Vec3 {
+        if self.sun_height() >= 0.0 {
+            self.sun_dir()
+        } else {
+            self.moon_dir()
+        }
+    }
+
+    /// Color (and intensity) of the directional light
+    pub fn light_color(&self) -> glam::Vec3 {
+        let height = self.sun_height();
+        sample(&SUN_LIGHT, height) + MOON_LIGHT * smoothstep(0.0, 0.2, -height)
+    }
+
+    pub fn ambient_color(&self) -> glam::Vec3 {
+        sample(&AMBIENT, self.sun_height())
+    }
+
+    pub fn zenith_color(&self) -> glam::Vec3 {
+        sample(&ZENITH, self.sun_height())
+    }
+
+    pub fn horizon_color(&self) -> glam::Vec3 {
+        sample(&HORIZON, self.sun_height())
+    }
+
+    /// Distant geometry fades into the horizon color, so that it blends into the sky
+    pub fn fog_color(&self) -> glam::Vec3 {
+        self.horizon_color()
+    }
+
+    pub fn sun_color(&self) -> glam::Vec3 {
+        sample(&SUN_DISC, self.sun_height())
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,44 +1,80 @@
 pub mod element;
+mod font;
 pub mod renderer;
 
 pub use element::*;
 use glow::Texture;
 pub use renderer::*;
 
-use crate::world::block::BlockType;
+use crate::{atlas::TextureLayout, world::block::BlockType};
 
 use super::mesh::Face;
 
 pub fn inventory(
     frame: &mut UiFrame,
-    types: &Vec<BlockType>,
-    active_type: &usize,
+    types: &[BlockType],
+    active_type: usize,
     atlas: &Texture,
+    layout: &TextureLayout,
+    viewport: glam::Vec2,
 ) {
     // 2 px outer padding
     // 2 px padding around sprite
     // 36px sprite
 
     let extend = glam::vec2((40 * 10 + 8) as f32, 48.0);
-    let tl = glam::vec2(0.5, 1.0) * (glam::vec2(600.0, 400.0) - extend);
+    // Centered at the bottom of the viewport
+    let tl = glam::vec2(0.5, 1.0) * (viewport - extend);
 
     // Draw background
     frame.rect(UiRect::new(tl, extend), UiMaterial::WHITE);
-    let base = 100u32;
 
     for (i, t) in types.iter().take(10).enumerate() {
+        let slot = tl + glam::vec2((i * 40) as f32, 0.0);
+
         // Maybe add active marker
-        if *active_type == i {
-            let rect = UiRect::from_coords((base - 2u32) + i as u32 * 40, 400 - 44, 40, 40);
+        if active_type == i {
+            let rect = UiRect::new(slot + glam::vec2(2.0, 4.0), glam::Vec2::splat(40.0));
             frame.rect(rect, UiMaterial::BLACK)
         }
 
-        let rect = UiRect::from_coords(base + i as u32 * 40, 400 - 42, 36, 36);
+        let rect = UiRect::new(slot + glam::vec2(4.0, 6.0), glam::Vec2::splat(36.0));
         let texture = t
             .textures()
             .expect("failed to get texture for item")
             .for_face(&Face::PositiveX);
-        let tex_coord = UiRect::new(texture.base(), texture.extend());
+        let (base, extend) = layout.uv(&texture);
+        let tex_coord = UiRect::new(base, extend);
         frame.rect_with_tex(rect, tex_coord, UiMaterial::Sprite(*atlas))
     }
 }
+
+/// The first line of each error (eg. of a shader that failed to compile) on top of the viewport
+pub fn errors(frame: &mut UiFrame, errors: &[String], viewport: glam::Vec2) {
+    const SCALE: f32 = 2.0;
+    const PADDING: f32 = 4.0;
+    let line_height = SCALE * (font::GLYPH_SIZE.y + 2.0);
+
+    let extend = glam::vec2(
+        viewport.x,
+        errors.len() as f32 * line_height + 2.0 * PADDING,
+    );
+    frame.rect(
+        UiRect::new(glam::Vec2::ZERO, extend),
+        UiMaterial::Solid(glam::vec4(0.5, 0.05, 0.05, 1.0)),
+    );
+
+    // Lines that are too long are cut off
+    let max_chars = ((viewport.x - 2.0 * PADDING) / (SCALE * font::ADVANCE)).max(0.0) as usize;
+    for (i, error) in errors.iter().enumerate() {
+        let line: String = error
+            .lines()
+            .next()
+            .unwrap_or("")
+            .chars()
+            .take(max_chars)
+            .collect();
+        let tl = glam::vec2(PADDING, PADDING + i as f32 * line_height);
+        frame.text(tl, &line, SCALE);
+    }
+}
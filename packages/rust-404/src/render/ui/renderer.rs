@@ -1,10 +1,17 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
 use anyhow::anyhow;
 use bytemuck::{Pod, Zeroable};
 use glow::{Buffer, HasContext, Program, Texture, VertexArray};
 
-use crate::render::{Renderer, FLOAT_SIZE};
+#[cfg(feature = "dev")]
+use crate::render::ShaderSources;
+use crate::render::{preprocessor::Preprocessor, Handle, Renderer, FLOAT_SIZE};
 
-use super::Color;
+use super::{font, Color};
 
 #[derive(Debug, Clone, Copy, Zeroable, Pod)]
 #[repr(C)]
@@ -17,6 +24,8 @@ struct UiVertex {
 pub enum UiMaterial {
     Solid(Color),
     Sprite(Texture),
+    /// Glyphs of the built in font, see `UiFrame::text`
+    Text,
 }
 
 impl UiMaterial {
@@ -38,12 +47,6 @@ impl UiRect {
     pub fn new(tl: glam::Vec2, extend: glam::Vec2) -> Self {
         Self { tl, extend }
     }
-    pub fn from_coords(x: u32, y: u32, width: u32, height: u32) -> Self {
-        Self {
-            tl: glam::uvec2(x, y).as_vec2(),
-            extend: glam::uvec2(width, height).as_vec2(),
-        }
-    }
 }
 
 #[derive(Debug, Clone)]
@@ -59,6 +62,12 @@ pub struct UiFrame {
     geometry: Vec<UiGeometry>,
 }
 
+impl Default for UiFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UiFrame {
     pub fn new() -> Self {
         Self {
@@ -86,6 +95,28 @@ impl UiFrame {
         });
     }
 
+    /// A single line of `text` in the built in font, with its top left corner at `tl` and every
+    /// pixel of the font `scale` pixels large. Returns the width of the line
+    pub fn text(&mut self, tl: glam::Vec2, text: &str, scale: f32) -> f32 {
+        let first = self.indices.len() as i32;
+        let mut x = 0.0;
+        for c in text.chars() {
+            if !c.is_whitespace() {
+                let rect = UiRect::new(tl + glam::vec2(x, 0.0), scale * font::GLYPH_SIZE);
+                self.add_rect_with_tex_coord(rect, font::tex_coord(c));
+            }
+            x += scale * font::ADVANCE;
+        }
+
+        // The whole line is drawn at once
+        self.geometry.push(UiGeometry {
+            first,
+            count: self.indices.len() as i32 - first,
+            material: UiMaterial::Text,
+        });
+        x
+    }
+
     fn add_rect(&mut self, rect: UiRect) -> (i32, i32) {
         self.add_rect_with_tex_coord(rect, UiRect::DEFAULT_TEX)
     }
@@ -117,42 +148,46 @@ impl UiFrame {
 }
 
 pub struct UiRenderer {
-    program: Program,
-    view_projection: glam::Mat4,
-    vertex_array: VertexArray,
-    vertex_buffer: Buffer,
-    index_buffer: Buffer,
+    program: RefCell<Handle<Program>>,
+    font: Handle<Texture>,
+    view_projection: Cell<glam::Mat4>,
+    vertex_array: Handle<VertexArray>,
+    vertex_buffer: Handle<Buffer>,
+    index_buffer: Handle<Buffer>,
 }
 
 const UI_VERTEX_CODE: &'static str = include_str!("../shaders/ui.vert");
 const UI_FRAGMENT_CODE: &'static str = include_str!("../shaders/ui.frag");
 
 impl UiRenderer {
-    pub unsafe fn new(context: &glow::Context) -> anyhow::Result<Self> {
-        let program = {
-            let vert = Renderer::compile_shader(&context, glow::VERTEX_SHADER, UI_VERTEX_CODE)?;
-            let frag = Renderer::compile_shader(&context, glow::FRAGMENT_SHADER, UI_FRAGMENT_CODE)?;
-            Renderer::link_program(&context, vert, frag)?
-        };
-
-        let view_projection = glam::Mat4::orthographic_rh_gl(0.0, 600.0, 400.0, 0.0, -1.0, 1.0);
+    /// `size` is the size of the viewport in CSS pixels, eg. the coordinate space of the UI
+    pub(crate) unsafe fn new(
+        context: &Rc<glow::Context>,
+        preprocessor: &Preprocessor,
+        size: glam::Vec2,
+    ) -> anyhow::Result<Self> {
+        let program =
+            Renderer::compile_program(context, preprocessor, UI_VERTEX_CODE, UI_FRAGMENT_CODE)?;
 
         let vertex_buffer = context
             .create_buffer()
             .map_err(|e| anyhow!("failed to create buffer: {}", e))?;
+        let vertex_buffer = Handle::new(context, vertex_buffer, glow::Context::delete_buffer);
 
         let index_buffer = context
             .create_buffer()
             .map_err(|e| anyhow!("failed to create buffer: {}", e))?;
+        let index_buffer = Handle::new(context, index_buffer, glow::Context::delete_buffer);
 
         let vertex_array = context
             .create_vertex_array()
             .map_err(|e| anyhow!("failed to create buffer: {}", e))?;
+        let vertex_array = Handle::new(context, vertex_array, glow::Context::delete_vertex_array);
 
         // TODO: Set those up
-        context.bind_vertex_array(Some(vertex_array));
-        context.bind_buffer(glow::ARRAY_BUFFER, Some(vertex_buffer));
-        context.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(index_buffer));
+        context.bind_vertex_array(Some(vertex_array.raw()));
+        context.bind_buffer(glow::ARRAY_BUFFER, Some(vertex_buffer.raw()));
+        context.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(index_buffer.raw()));
 
         const ATTRIB_DATA: [i32; 2] = [2, 2];
         let total_size = ATTRIB_DATA.iter().sum::<i32>() * FLOAT_SIZE;
@@ -175,36 +210,91 @@ impl UiRenderer {
             .enumerate()
             .for_each(|(i, _)| context.enable_vertex_attrib_array(i as u32));
 
+        // The glyphs are drawn with sharp pixels
+        let image = font::image();
+        let font = context
+            .create_texture()
+            .map_err(|e| anyhow!("failed to create font texture: {}", e))?;
+        let font = Handle::new(context, font, glow::Context::delete_texture);
+        context.bind_texture(glow::TEXTURE_2D, Some(font.raw()));
+        context.tex_image_2d(
+            glow::TEXTURE_2D,
+            0,
+            glow::RGBA as _,
+            image.width as _,
+            image.height as _,
+            0,
+            glow::RGBA,
+            glow::UNSIGNED_BYTE,
+            Some(&image.pixels),
+        );
+        for filter in [glow::TEXTURE_MIN_FILTER, glow::TEXTURE_MAG_FILTER] {
+            context.tex_parameter_i32(glow::TEXTURE_2D, filter, glow::NEAREST as _);
+        }
+
         Ok(Self {
-            program,
-            view_projection,
+            program: RefCell::new(program),
+            font,
+            view_projection: Cell::new(Self::projection(size)),
             vertex_array,
             vertex_buffer,
             index_buffer,
         })
     }
 
-    pub unsafe fn render(&self, context: &glow::Context, frame: UiFrame) {
+    fn projection(size: glam::Vec2) -> glam::Mat4 {
+        glam::Mat4::orthographic_rh_gl(0.0, size.x, size.y, 0.0, -1.0, 1.0)
+    }
+
+    pub fn resize(&self, size: glam::Vec2) {
+        self.view_projection.set(Self::projection(size));
+    }
+
+    /// Recompiles the program from `sources`, it is kept as it was if that fails
+    #[cfg(feature = "dev")]
+    pub(crate) unsafe fn reload_shaders(
+        &self,
+        context: &Rc<glow::Context>,
+        preprocessor: &Preprocessor,
+        sources: &ShaderSources,
+        errors: &mut Vec<String>,
+    ) {
+        let source =
+            |file: &str, code: &'static str| sources.get(file).map_or(code, String::as_str);
+        let vertex = source("ui.vert", UI_VERTEX_CODE);
+        let fragment = source("ui.frag", UI_FRAGMENT_CODE);
+        match Renderer::compile_program(context, preprocessor, vertex, fragment) {
+            Ok(program) => *self.program.borrow_mut() = program,
+            Err(e) => errors.push(format!("ui.vert + ui.frag: {}", e)),
+        }
+    }
+
+    pub(crate) unsafe fn render(&self, context: &glow::Context, frame: UiFrame) {
         context.disable(glow::DEPTH_TEST);
 
-        context.use_program(Some(self.program));
+        let program = self.program.borrow().raw();
+        context.use_program(Some(program));
 
         // Setup view projection
-        let vp_loc = context.get_uniform_location(self.program, "view_projection");
-        context.uniform_matrix_4_f32_slice(vp_loc.as_ref(), false, self.view_projection.as_ref());
+        let vp_loc = context.get_uniform_location(program, "view_projection");
+        context.uniform_matrix_4_f32_slice(
+            vp_loc.as_ref(),
+            false,
+            self.view_projection.get().as_ref(),
+        );
 
-        let color_loc = context.get_uniform_location(self.program, "color");
+        let color_loc = context.get_uniform_location(program, "color");
 
-        context.bind_vertex_array(Some(self.vertex_array));
+        context.bind_vertex_array(Some(self.vertex_array.raw()));
 
-        context.bind_buffer(glow::ARRAY_BUFFER, Some(self.vertex_buffer));
+        context.bind_buffer(glow::ARRAY_BUFFER, Some(self.vertex_buffer.raw()));
         context.buffer_data_u8_slice(
             glow::ARRAY_BUFFER,
             bytemuck::cast_slice(&frame.buffer),
             glow::STREAM_DRAW,
         );
 
-        context.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(self.index_buffer));
+        context.bind_buffer(glow::ELEMENT_ARRAY_BUFFER, Some(self.index_buffer.raw()));
         context.buffer_data_u8_slice(
             glow::ELEMENT_ARRAY_BUFFER,
             bytemuck::cast_slice(&frame.indices),
@@ -220,12 +310,13 @@ impl UiRenderer {
             let t = match &material {
                 UiMaterial::Solid(_) => None,
                 UiMaterial::Sprite(t) => Some(t).cloned(),
+                UiMaterial::Text => Some(self.font.raw()),
             };
             context.bind_texture(glow::TEXTURE_2D, t);
 
             let color = match material {
                 UiMaterial::Solid(color) => color,
-                UiMaterial::Sprite(_) => glam::Vec4::ZERO,
+                UiMaterial::Sprite(_) | UiMaterial::Text => glam::Vec4::ZERO,
             };
             context.uniform_4_f32_slice(color_loc.as_ref(), color.as_ref());
 
@@ -1,4 +1,5 @@
 // A macro to provide `println!(..)`-style syntax for `console.log` logging.
+#[cfg(target_arch = "wasm32")]
 #[allow(unused_macros)]
 macro_rules! log {
     ( $( $t:tt )* ) => {
@@ -6,6 +7,16 @@ macro_rules! log {
     }
 }
 
+// Natively there is no console, so it goes to stderr instead
+#[cfg(not(target_arch = "wasm32"))]
+#[allow(unused_macros)]
+macro_rules! log {
+    ( $( $t:tt )* ) => {
+        eprintln!( $( $t )* );
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
 pub fn set_panic_hook() {
     // When the `console_error_panic_hook` feature is enabled, we can call the
     // `set_panic_hook` function at least once during initialization, and then
@@ -1,6 +1,9 @@
 use rand::{distributions::Standard, prelude::Distribution};
 
-use crate::{atlas::BlockTexture, render::mesh::Face};
+use crate::{
+    atlas::BlockTexture,
+    render::{frustum::Aabb, mesh::Face, RenderLayer},
+};
 use enum_iterator::IntoEnumIterator;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, IntoEnumIterator)]
@@ -23,6 +26,109 @@ pub enum BlockType {
     Diamond,
     Sand,
     Brick,
+    Glass,
+    Ice,
+    Water,
+    Lava,
+    Leaves,
+    TallGrass,
+    GrassTuft,
+    Shrub,
+    SmallShrub,
+    Wheat,
+    MushroomBrown,
+    MushroomRed,
+    MushroomTan,
+    StoneSlab,
+    WoodStairs,
+    WoodFence,
+    StoneFence,
+    Rail,
+    RailCorner,
+}
+
+/// The geometry a block is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockShape {
+    Cube,
+    /// Two diagonal quads, eg. for plants
+    Cross,
+    /// Lower half of a block
+    Slab,
+    /// A slab with a step at the back (towards +Z, before it is rotated by its `Facing`)
+    Stairs,
+    /// A thin panel (along the x axis, before it is rotated), that shows the fence sprite on
+    /// both sides
+    Fence,
+    /// A flat plate on the ground
+    Rail,
+}
+
+impl BlockShape {
+    /// The boxes the shape consists of, relative to the center of the block (eg. in [-0.5; 0.5])
+    /// and rotated by `facing`
+    pub fn boxes(&self, facing: Facing) -> Vec<Aabb> {
+        let rotation = facing.rotation();
+        let aabb = |min: [f32; 3], max: [f32; 3]| {
+            let (a, b) = (
+                rotation * glam::Vec3::from(min),
+                rotation * glam::Vec3::from(max),
+            );
+            Aabb::new(a.min(b), a.max(b))
+        };
+        match self {
+            BlockShape::Cube => vec![aabb([-0.5; 3], [0.5; 3])],
+            // Only used for picking, the cross itself is not a box
+            BlockShape::Cross => vec![aabb([-0.35, -0.5, -0.35], [0.35, 0.35, 0.35])],
+            BlockShape::Slab => vec![aabb([-0.5; 3], [0.5, 0.0, 0.5])],
+            BlockShape::Stairs => vec![
+                aabb([-0.5; 3], [0.5, 0.0, 0.5]),
+                aabb([-0.5, 0.0, 0.0], [0.5; 3]),
+            ],
+            BlockShape::Fence => vec![aabb([-0.5, -0.5, -0.0625], [0.5, 0.5, 0.0625])],
+            BlockShape::Rail => vec![aabb([-0.5; 3], [0.5, -0.4375, 0.5])],
+        }
+    }
+}
+
+/// Horizontal direction a block faces, eg. where the step of stairs is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Facing {
+    #[default]
+    PositiveZ,
+    PositiveX,
+    NegativeZ,
+    NegativeX,
+}
+
+impl Facing {
+    /// The horizontal axis that is closest to `dir`, eg. the view direction when placing a block
+    pub fn from_dir(dir: glam::Vec3) -> Self {
+        match (dir.x.abs() > dir.z.abs(), dir.x > 0.0, dir.z > 0.0) {
+            (true, true, _) => Facing::PositiveX,
+            (true, false, _) => Facing::NegativeX,
+            (false, _, true) => Facing::PositiveZ,
+            (false, _, false) => Facing::NegativeZ,
+        }
+    }
+
+    /// Rotation around the y axis that turns +Z into this direction. Its entries are all 0 or ±1,
+    /// so rotated vertices still line up exactly with the ones of their neighbors
+    pub fn rotation(&self) -> glam::Mat3 {
+        let (x, z) = match self {
+            Facing::PositiveZ => (glam::Vec3::X, glam::Vec3::Z),
+            Facing::PositiveX => (-glam::Vec3::Z, glam::Vec3::X),
+            Facing::NegativeZ => (-glam::Vec3::X, -glam::Vec3::Z),
+            Facing::NegativeX => (glam::Vec3::Z, -glam::Vec3::X),
+        };
+        glam::Mat3::from_cols(x, glam::Vec3::Y, z)
+    }
+}
+
+/// Everything about a block besides its type, eg. how it has been placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlockState {
+    pub facing: Facing,
 }
 
 impl Distribution<BlockType> for Standard {
@@ -36,6 +142,25 @@ impl Distribution<BlockType> for Standard {
     }
 }
 
+/// What grows on top of a grass block, mostly nothing
+pub struct Vegetation;
+
+impl Distribution<BlockType> for Vegetation {
+    fn sample<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> BlockType {
+        match rng.gen_range(0..100) {
+            0..=7 => BlockType::TallGrass,
+            8..=12 => BlockType::GrassTuft,
+            13..=14 => BlockType::Shrub,
+            15..=16 => BlockType::SmallShrub,
+            17 => BlockType::MushroomRed,
+            18 => BlockType::MushroomBrown,
+            19 => BlockType::MushroomTan,
+            20 => BlockType::Wheat,
+            _ => BlockType::Air,
+        }
+    }
+}
+
 pub enum BlockTextures {
     Uniform(BlockTexture),
     SideTopBottom {
@@ -83,6 +208,55 @@ fn side_top_bottom(
 }
 
 impl BlockType {
+    /// Whether the block hides everything behind it
+    pub fn is_opaque(&self) -> bool {
+        self.textures().is_some()
+            && self.layer() == RenderLayer::Opaque
+            && self.shape() == BlockShape::Cube
+    }
+
+    pub fn shape(&self) -> BlockShape {
+        match self {
+            BlockType::TallGrass
+            | BlockType::GrassTuft
+            | BlockType::Shrub
+            | BlockType::SmallShrub
+            | BlockType::Wheat
+            | BlockType::MushroomBrown
+            | BlockType::MushroomRed
+            | BlockType::MushroomTan => BlockShape::Cross,
+            BlockType::StoneSlab => BlockShape::Slab,
+            BlockType::WoodStairs => BlockShape::Stairs,
+            BlockType::WoodFence | BlockType::StoneFence => BlockShape::Fence,
+            BlockType::Rail | BlockType::RailCorner => BlockShape::Rail,
+            _ => BlockShape::Cube,
+        }
+    }
+
+    pub fn layer(&self) -> RenderLayer {
+        match self {
+            BlockType::Leaves => RenderLayer::Cutout,
+            _ if matches!(
+                self.shape(),
+                BlockShape::Cross | BlockShape::Fence | BlockShape::Rail
+            ) =>
+            {
+                RenderLayer::Cutout
+            }
+            BlockType::Glass | BlockType::Ice | BlockType::Water => RenderLayer::Translucent,
+            _ => RenderLayer::Opaque,
+        }
+    }
+
+    /// Whether the face of this block towards `neighbor` is visible
+    pub fn face_visible(&self, neighbor: BlockType) -> bool {
+        match self.layer() {
+            // Faces between blocks of the same translucent type (eg. inside of water) are hidden
+            RenderLayer::Translucent => !neighbor.is_opaque() && neighbor != *self,
+            _ => !neighbor.is_opaque(),
+        }
+    }
+
     pub fn textures(&self) -> Option<BlockTextures> {
         use BlockTexture::*;
         match &*self {
@@ -112,6 +286,25 @@ impl BlockType {
             BlockType::Diamond => uniform(StoneDiamond),
             BlockType::Sand => uniform(Sand),
             BlockType::Brick => uniform(BrickRed),
+            BlockType::Glass => uniform(Glass),
+            BlockType::Ice => uniform(Ice),
+            BlockType::Water => uniform(Water),
+            BlockType::Lava => uniform(Lava),
+            BlockType::Leaves => uniform(LeavesTransparent),
+            BlockType::TallGrass => uniform(Grass4),
+            BlockType::GrassTuft => uniform(Grass3),
+            BlockType::Shrub => uniform(Grass2),
+            BlockType::SmallShrub => uniform(Grass1),
+            BlockType::Wheat => uniform(WheatStage4),
+            BlockType::MushroomBrown => uniform(MushroomBrown),
+            BlockType::MushroomRed => uniform(MushroomRed),
+            BlockType::MushroomTan => uniform(MushroomTan),
+            BlockType::StoneSlab => uniform(Stone),
+            BlockType::WoodStairs => uniform(Wood),
+            BlockType::WoodFence => uniform(FenceWood),
+            BlockType::StoneFence => uniform(FenceStone),
+            BlockType::Rail => uniform(TrackStraight),
+            BlockType::RailCorner => uniform(TrackCorner),
         }
     }
 }
@@ -1,19 +1,31 @@
 use crate::{
+    atlas::TextureLayout,
     render::{
-        mesh::{build_face, Face},
-        Vertex,
+        mesh::{build_box_face, build_cross, build_face, build_lod_face, Face},
+        RenderLayer, Vertex,
     },
-    world::block::BlockType,
+    world::block::{BlockShape, BlockState, BlockType, Vegetation},
 };
 use anyhow::anyhow;
-use noise::{NoiseFn, Seedable};
+use noise::NoiseFn;
 use rand::Rng;
 
-// NOTE: That has to be kept in sync with picking.vert
+// Defined in the shaders as well, see `Preprocessor`
 pub const CHUNK_SIZE: usize = 16;
 
+/// Number of detail levels, where level `n` merges 2^n blocks along each axis into one
+pub const LOD_LEVELS: usize = 3;
+
+/// Vertices of a chunk split up by `RenderLayer`
+pub type LayeredVertices = [Vec<Vertex>; RenderLayer::LAYERS.len()];
+
+/// Horizontal scale of the terrain noise, in blocks
+const NOISE_SCALE: f64 = CHUNK_SIZE as f64 + 2.0;
+
 pub struct Chunk {
     blocks: [[[BlockType; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+    /// The state of each block, only used by shapes that can be rotated
+    states: [[[BlockState; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
 }
 
 fn gen_3d_range(from: i32, to: i32) -> impl Iterator<Item = glam::IVec3> {
@@ -23,16 +35,30 @@ fn gen_3d_range(from: i32, to: i32) -> impl Iterator<Item = glam::IVec3> {
 }
 
 impl Chunk {
-    pub fn new() -> Self {
+    /// World space position of the chunk with the key `key` (eg. its minimum corner)
+    pub fn origin(key: glam::IVec2) -> glam::IVec3 {
+        glam::ivec3(key.x, 0, key.y) * CHUNK_SIZE as i32
+    }
+
+    /// Splits a world space block position into the key of the chunk and the position in it
+    pub fn locate(pos: glam::IVec3) -> (glam::IVec2, glam::IVec3) {
+        let size = CHUNK_SIZE as i32;
+        let key = glam::ivec2(pos.x.div_euclid(size), pos.z.div_euclid(size));
+        (key, pos - Self::origin(key))
+    }
+
+    /// `rng` decides where the plants are scattered
+    pub fn new(key: glam::IVec2, noise: &impl NoiseFn<[f64; 2]>, rng: &mut impl Rng) -> Self {
         let mut blocks = [[[BlockType::Air; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE];
 
-        let perlin = noise::Perlin::new();
-        let perlin = perlin.set_seed(rand::thread_rng().gen_range(0..123456));
-        let size = CHUNK_SIZE as f64 + 2.0;
+        let origin = Self::origin(key);
         for x in 0..CHUNK_SIZE {
             for z in 0..CHUNK_SIZE {
-                let height = perlin.get([(x as f64 + 1.0) / size, (z as f64 + 1.0) / size])
-                    * (CHUNK_SIZE as f64 / 2.0)
+                let (world_x, world_z) = (origin.x + x as i32, origin.z + z as i32);
+                let height = noise.get([
+                    (world_x as f64 + 1.0) / NOISE_SCALE,
+                    (world_z as f64 + 1.0) / NOISE_SCALE,
+                ]) * (CHUNK_SIZE as f64 / 2.0)
                     + (CHUNK_SIZE as f64 / 2.0);
                 let height = height.floor() as i32;
                 for y in 0..CHUNK_SIZE {
@@ -44,15 +70,29 @@ impl Chunk {
                     };
                     blocks[x][y][z] = block_type;
                 }
+
+                // Scatter some plants on top of the grass
+                let above = height + 1;
+                if height >= 0 && above < CHUNK_SIZE as i32 {
+                    blocks[x][above as usize][z] = rng.sample(Vegetation);
+                }
             }
         }
 
         // blocks.iter_mut().for_each(|b| *b = rand::random());
 
-        Chunk { blocks }
+        Chunk {
+            blocks,
+            states: [[[BlockState::default(); CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE],
+        }
     }
 
-    pub fn set(&mut self, pos: glam::IVec3, block_type: BlockType) -> anyhow::Result<()> {
+    pub fn set(
+        &mut self,
+        pos: glam::IVec3,
+        block_type: BlockType,
+        state: BlockState,
+    ) -> anyhow::Result<()> {
         if pos
             .as_ref()
             .iter()
@@ -61,11 +101,17 @@ impl Chunk {
             Err(anyhow!("pos: {:?} is out of bounds for chunk", pos))
         } else {
             self.blocks[pos.x as usize][pos.y as usize][pos.z as usize] = block_type;
+            self.states[pos.x as usize][pos.y as usize][pos.z as usize] = state;
             Ok(())
         }
     }
 
-    fn sample_vec(&self, vec: glam::IVec3) -> Option<BlockType> {
+    /// All block positions inside of a chunk
+    pub fn positions() -> impl Iterator<Item = glam::IVec3> {
+        gen_3d_range(0, CHUNK_SIZE as i32)
+    }
+
+    pub fn get(&self, vec: glam::IVec3) -> Option<BlockType> {
         if vec
             .as_ref()
             .iter()
@@ -77,18 +123,55 @@ impl Chunk {
         }
     }
 
-    pub fn chunk_vertices(&self) -> Vec<Vertex> {
-        let mut vertices = Vec::new();
-        for pos in gen_3d_range(0, CHUNK_SIZE as i32) {
-            if let Some(block) = self.sample_vec(pos) {
+    pub fn state(&self, vec: glam::IVec3) -> Option<BlockState> {
+        self.get(vec)?;
+        Some(self.states[vec.x as usize][vec.y as usize][vec.z as usize])
+    }
+
+    pub fn chunk_vertices(&self, layout: &TextureLayout) -> LayeredVertices {
+        let mut vertices = LayeredVertices::default();
+        for pos in Self::positions() {
+            if let Some(block) = self.get(pos) {
                 if let Some(textures) = block.textures() {
+                    let vertices = &mut vertices[block.layer().index()];
+                    match block.shape() {
+                        BlockShape::Cube => (),
+                        BlockShape::Cross => {
+                            // Crosses are never culled, since they do not fill the block
+                            let texture = textures.for_face(&Face::PositiveX);
+                            build_cross(vertices, &pos, texture, layout);
+                            continue;
+                        }
+                        shape => {
+                            // The faces are built unrotated and rotated afterwards, so that the
+                            // textures turn along with the shape
+                            let rotation = self.state(pos).unwrap_or_default().facing.rotation();
+                            for bounds in shape.boxes(Default::default()).iter() {
+                                for face in Face::FACES.iter() {
+                                    // Only faces on the border of the block can be hidden
+                                    let normal = face.normal();
+                                    let on_border = bounds.max.dot(normal) >= 0.5
+                                        || bounds.min.dot(normal) >= 0.5;
+                                    let dir = (rotation * normal).round().as_ivec3();
+                                    let neighbor = self.get(pos + dir).unwrap_or(BlockType::Air);
+                                    if !on_border || !neighbor.is_opaque() {
+                                        let texture = textures.for_face(face);
+                                        build_box_face(
+                                            vertices, face, &pos, bounds, &rotation, texture,
+                                            layout,
+                                        )
+                                    }
+                                }
+                            }
+                            continue;
+                        }
+                    }
                     for face in Face::FACES.iter() {
-                        let neighbor = self.sample_vec(pos + face.neighbor_dir());
-                        if neighbor.is_none()
-                            || neighbor.map(|v| v.textures().is_none()).unwrap_or(false)
-                        {
+                        let neighbor = self.get(pos + face.neighbor_dir());
+                        // Everything outside of the chunk counts as air
+                        if block.face_visible(neighbor.unwrap_or(BlockType::Air)) {
                             let texture = textures.for_face(face);
-                            build_face(&mut vertices, face, &pos, texture)
+                            build_face(vertices, face, &pos, texture, layout)
                         }
                     }
                 }
@@ -96,4 +179,78 @@ impl Chunk {
         }
         vertices
     }
+
+    /// Vertices of a lower detail version of the chunk, where each cube consists of
+    /// `2^lod` blocks along each axis. Faces at the chunk border are always generated, which
+    /// hides the seams to neighboring chunks with a different level of detail. Only opaque blocks
+    /// are kept in the lower levels, since details like glass or leaves are not visible anyway
+    pub fn lod_vertices(&self, lod: usize, layout: &TextureLayout) -> LayeredVertices {
+        if lod == 0 {
+            return self.chunk_vertices(layout);
+        }
+
+        let scale = 1i32 << lod;
+        let size = CHUNK_SIZE as i32 / scale;
+        let index = |c: glam::IVec3| ((c.x * size + c.y) * size + c.z) as usize;
+
+        let cells: Vec<_> = gen_3d_range(0, size)
+            .map(|cell| self.downsample(cell * scale, scale))
+            .collect();
+        let cell = |c: glam::IVec3| {
+            if c.as_ref().iter().any(|c| *c < 0 || *c >= size) {
+                BlockType::Air
+            } else {
+                cells[index(c)]
+            }
+        };
+
+        let mut vertices = LayeredVertices::default();
+        let opaque = &mut vertices[RenderLayer::Opaque.index()];
+        for pos in gen_3d_range(0, size) {
+            if let Some(textures) = cell(pos).textures() {
+                for face in Face::FACES.iter() {
+                    if !cell(pos + face.neighbor_dir()).is_opaque() {
+                        let texture = textures.for_face(face);
+                        build_lod_face(opaque, face, &(pos * scale), scale, texture, layout)
+                    }
+                }
+            }
+        }
+        vertices
+    }
+
+    /// Merges the `scale`^3 blocks starting at `min` into one. The cell is solid if at least half
+    /// of the blocks are and then takes the most common type of the top most blocks, so that
+    /// eg. grass stays visible from the distance
+    fn downsample(&self, min: glam::IVec3, scale: i32) -> BlockType {
+        let mut opaque = 0;
+        let mut tops: Vec<(BlockType, usize)> = Vec::new();
+
+        for x in 0..scale {
+            for z in 0..scale {
+                let column = (0..scale)
+                    .rev()
+                    .filter_map(|y| self.get(min + glam::ivec3(x, y, z)))
+                    .filter(|b| b.is_opaque());
+
+                let mut column = column.peekable();
+                if let Some(top) = column.peek().copied() {
+                    match tops.iter_mut().find(|(t, _)| *t == top) {
+                        Some((_, count)) => *count += 1,
+                        None => tops.push((top, 1)),
+                    }
+                }
+                opaque += column.count();
+            }
+        }
+
+        if 2 * opaque < (scale * scale * scale) as usize {
+            return BlockType::Air;
+        }
+
+        tops.into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(t, _)| t)
+            .unwrap_or(BlockType::Air)
+    }
 }
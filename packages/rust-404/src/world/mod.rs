@@ -1,35 +1,171 @@
 use crate::{
     input::{Button, EventListener, InputEvent, Key},
-    render::{Face, Mesh, RenderTask, Renderer},
+    render::{
+        camera::Camera, frustum::Aabb, mesh::sort_faces, Face, Mesh, MeshArena, RenderLayer,
+        RenderTask, Renderer, Vertex,
+    },
 };
 use enum_iterator::IntoEnumIterator;
-use std::{collections::HashMap, rc::Rc};
+use noise::Seedable;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{cmp::Ordering, collections::HashMap, rc::Rc};
 
 pub mod block;
 pub mod chunk;
+pub mod visibility;
 
 pub use block::*;
 pub use chunk::*;
+pub use visibility::*;
+
+/// How far the world is visible (in chunks), everything further away is hidden by fog
+pub const RENDER_DISTANCE: usize = 4;
+
+/// Number of vertices of the buffers the chunk meshes are allocated from
+const ARENA_PAGE_SIZE: i32 = 1 << 16;
+
+/// Distances (in blocks) from which on the next lower level of detail is used
+const LOD_DISTANCES: [f32; LOD_LEVELS - 1] = [2.0 * CHUNK_SIZE as f32, 3.0 * CHUNK_SIZE as f32];
+
+/// The meshes of one level of detail of a chunk
+struct ChunkMesh {
+    /// One mesh per non-empty layer, together with its bounds in chunk space
+    layers: Vec<(RenderLayer, Mesh, Aabb)>,
+    /// The vertices of the translucent layer, to sort its faces again when the camera moves
+    translucent: Vec<Vertex>,
+}
+
+impl ChunkMesh {
+    fn new(renderer: &Renderer, arena: &MeshArena, chunk: &Chunk, lod: usize) -> Self {
+        let mut mesh = Self {
+            layers: Vec::new(),
+            translucent: Vec::new(),
+        };
+        mesh.update(renderer, arena, chunk, lod);
+        mesh
+    }
+
+    /// Rebuilds the meshes, the ones of layers that are still there keep their storage
+    fn update(&mut self, renderer: &Renderer, arena: &MeshArena, chunk: &Chunk, lod: usize) {
+        let mut previous = std::mem::take(&mut self.layers);
+        let layers = chunk.lod_vertices(lod, &renderer.texture_layout());
+        for (layer, vertices) in RenderLayer::LAYERS.iter().zip(layers.iter()) {
+            // The mesh of a layer that became empty is dropped
+            let bounds = match Aabb::from_vertices(vertices) {
+                Some(bounds) => bounds,
+                None => continue,
+            };
+            let mesh = match previous.iter().position(|(l, _, _)| l == layer) {
+                Some(i) => {
+                    let (_, mut mesh, _) = previous.swap_remove(i);
+                    renderer
+                        .update_mesh(&mut mesh, vertices)
+                        .expect("failed to update mesh");
+                    mesh
+                }
+                None => renderer
+                    .create_mesh_in(arena, vertices)
+                    .expect("failed to create mesh"),
+            };
+            self.layers.push((*layer, mesh, bounds));
+        }
+        let [.., translucent] = layers;
+        self.translucent = translucent;
+    }
+
+    /// Orders the translucent faces back to front as seen from `eye` (in chunk space)
+    fn sort_translucent(&mut self, renderer: &Renderer, eye: glam::Vec3) {
+        let mesh = match self
+            .layers
+            .iter_mut()
+            .find(|(l, _, _)| *l == RenderLayer::Translucent)
+        {
+            Some((_, mesh, _)) => mesh,
+            None => return,
+        };
+        sort_faces(&mut self.translucent, eye);
+        renderer
+            .update_mesh(mesh, &self.translucent)
+            .expect("failed to update mesh");
+    }
+}
+
+/// A chunk together with its gpu representation
+struct LoadedChunk {
+    chunk: Chunk,
+    /// One mesh per level of detail, starting with the full detail one
+    lods: Vec<ChunkMesh>,
+    visibility: Visibility,
+}
+
+impl LoadedChunk {
+    fn new(renderer: &Renderer, arena: &MeshArena, chunk: Chunk) -> Self {
+        Self {
+            lods: (0..LOD_LEVELS)
+                .map(|lod| ChunkMesh::new(renderer, arena, &chunk, lod))
+                .collect(),
+            visibility: Visibility::compute(&chunk),
+            chunk,
+        }
+    }
+
+    fn remesh(&mut self, renderer: &Renderer, arena: &MeshArena) {
+        for (lod, mesh) in self.lods.iter_mut().enumerate() {
+            mesh.update(renderer, arena, &self.chunk, lod);
+        }
+        self.visibility = Visibility::compute(&self.chunk);
+    }
+
+    /// `eye` is in world space, `key` is the one of this chunk
+    fn sort_translucent(&mut self, renderer: &Renderer, key: glam::IVec2, eye: glam::Vec3) {
+        let eye = eye - Chunk::origin(key).as_vec3();
+        for mesh in self.lods.iter_mut() {
+            mesh.sort_translucent(renderer, eye);
+        }
+    }
+}
 
 pub struct World {
     renderer: Rc<Renderer>,
-    chunks: HashMap<glam::IVec2, (Chunk, Mesh)>,
+    /// The chunk meshes are allocated from it
+    arena: MeshArena,
+    chunks: HashMap<glam::IVec2, LoadedChunk>,
     pub(crate) types: Vec<BlockType>,
     pub(crate) active_type: usize,
     pub(crate) last_picked: Option<(glam::Vec3, Face)>,
+    /// Direction newly placed blocks face, eg. away from the camera
+    pub(crate) facing: Facing,
+    lod_distances: Vec<f32>,
+    /// Where the translucent faces were sorted for the last time, see `update`
+    sorted_for: Option<glam::Vec3>,
 }
 
 impl World {
     pub fn new(renderer: Rc<Renderer>) -> Self {
-        let mut chunks: HashMap<_, _> = Default::default();
+        Self::with_seed(renderer, rand::thread_rng().gen_range(0..123456))
+    }
 
-        let chunk = Chunk::new();
+    /// The same seed always generates the same world
+    pub fn with_seed(renderer: Rc<Renderer>, seed: u32) -> Self {
+        let mut chunks: HashMap<_, _> = Default::default();
+        let arena = MeshArena::new(ARENA_PAGE_SIZE);
 
-        let mesh = renderer
-            .create_mesh(&chunk.chunk_vertices())
-            .expect("failed to create mesh");
+        let noise = noise::Perlin::new().set_seed(seed);
+        let mut rng = StdRng::seed_from_u64(seed as u64);
 
-        chunks.insert(glam::ivec2(0, 0), (chunk, mesh));
+        // Generate all chunks in a circle around the origin
+        let radius = RENDER_DISTANCE as i32;
+        for x in -radius..=radius {
+            for z in -radius..=radius {
+                if x * x + z * z > radius * radius {
+                    continue;
+                }
+                let key = glam::ivec2(x, z);
+                let chunk = Chunk::new(key, &noise, &mut rng);
+                let chunk = LoadedChunk::new(&renderer, &arena, chunk);
+                chunks.insert(key, chunk);
+            }
+        }
 
         let types = BlockType::into_enum_iter()
             .filter(|t| t.textures().is_some())
@@ -37,17 +173,135 @@ impl World {
 
         Self {
             renderer,
+            arena,
             chunks,
             types,
             active_type: 0,
             last_picked: None,
+            facing: Facing::default(),
+            lod_distances: LOD_DISTANCES.to_vec(),
+            sorted_for: None,
         }
     }
 
-    pub fn render<'a>(&'a self, task: &mut RenderTask<'a>) {
-        for (_, (_, mesh)) in self.chunks.iter() {
-            task.push(mesh);
+    /// Sorts the translucent faces of all chunks back to front, whenever the camera has entered
+    /// another block. Faces are only compared by their centers, so intersecting faces can still
+    /// be blended in the wrong order
+    pub fn update(&mut self, camera: &Camera) {
+        if matches!(self.sorted_for, Some(eye) if eye.round() == camera.pos.round()) {
+            return;
         }
+        for (key, loaded) in self.chunks.iter_mut() {
+            loaded.sort_translucent(&self.renderer, *key, camera.pos);
+        }
+        self.sorted_for = Some(camera.pos);
+    }
+
+    /// The block types of the inventory
+    pub fn types(&self) -> &[BlockType] {
+        &self.types
+    }
+
+    pub fn active_type(&self) -> usize {
+        self.active_type
+    }
+
+    /// Sets the distances (in blocks) from which on the next lower level of detail is used.
+    /// They are sorted, negative or non-finite ones are dropped, superfluous ones are ignored
+    /// and missing levels are never used. Only chunks in full detail can be picked, so the first
+    /// distance also limits how far away blocks can be picked
+    pub fn set_lod_distances(&mut self, distances: &[f32]) {
+        let mut distances: Vec<_> = distances
+            .iter()
+            .copied()
+            .filter(|d| d.is_finite() && *d >= 0.0)
+            .collect();
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        distances.truncate(LOD_LEVELS - 1);
+        self.lod_distances = distances;
+    }
+
+    fn select_lod(&self, key: glam::IVec2, camera_pos: glam::Vec3) -> usize {
+        // Distance to the closest point of the chunk
+        let min = Chunk::origin(key).as_vec3() - 0.5;
+        let closest = camera_pos.clamp(min, min + CHUNK_SIZE as f32);
+        let distance = closest.distance(camera_pos);
+
+        self.lod_distances
+            .iter()
+            .filter(|d| distance >= **d)
+            .count()
+    }
+
+    pub fn render<'a>(&'a self, task: &mut RenderTask<'a>, camera: &Camera) {
+        // Chunks that are hidden behind terrain are skipped entirely
+        let graph = self
+            .chunks
+            .iter()
+            .map(|(key, loaded)| (*key, loaded.visibility))
+            .collect();
+        let visible = visible_chunks(&graph, camera.pos);
+
+        for (key, LoadedChunk { lods, .. }) in self.chunks.iter() {
+            if matches!(&visible, Some(visible) if !visible.contains(key)) {
+                task.mark_occluded();
+                continue;
+            }
+            let transform = glam::Mat4::from_translation(Chunk::origin(*key).as_vec3());
+            // The vertices of the lower levels of detail don't know their exact block, so
+            // only chunks that are drawn in full detail can be picked
+            let lod = self.select_lod(*key, camera.pos);
+            let ChunkMesh { layers, .. } = &lods[lod];
+            for (layer, mesh, bounds) in layers.iter() {
+                task.push_culled(mesh, transform, bounds, *layer, lod == 0);
+            }
+        }
+    }
+
+    /// The block at the world space position `pos`, if it is inside of a loaded chunk
+    pub fn block(&self, pos: glam::IVec3) -> Option<BlockType> {
+        let (key, local) = Chunk::locate(pos);
+        self.chunks.get(&key)?.chunk.get(local)
+    }
+
+    /// The boxes the block at `pos` consists of (relative to its center), eg. for picking
+    pub fn boxes(&self, pos: glam::IVec3) -> Option<Vec<Aabb>> {
+        let (key, local) = Chunk::locate(pos);
+        let chunk = &self.chunks.get(&key)?.chunk;
+        let state = chunk.state(local)?;
+        Some(chunk.get(local)?.shape().boxes(state.facing))
+    }
+
+    /// Rebuilds the meshes of all chunks, eg. after the texture coordinates changed
+    pub fn remesh_all(&mut self) {
+        for (key, loaded) in self.chunks.iter_mut() {
+            loaded.remesh(&self.renderer, &self.arena);
+            if let Some(eye) = self.sorted_for {
+                loaded.sort_translucent(&self.renderer, *key, eye);
+            }
+        }
+    }
+
+    /// Sets the block at the world space position `pos` and remeshes the containing chunk.
+    /// Chunks are meshed on their own, where everything outside of a chunk counts as air, so
+    /// a block on a chunk border never changes the meshes (or the visibility) of the neighbors
+    pub fn set(
+        &mut self,
+        pos: glam::IVec3,
+        block_type: BlockType,
+        state: BlockState,
+    ) -> anyhow::Result<()> {
+        let (key, local) = Chunk::locate(pos);
+        let loaded = self
+            .chunks
+            .get_mut(&key)
+            .ok_or_else(|| anyhow::anyhow!("no chunk loaded at pos: {:?}", pos))?;
+        loaded.chunk.set(local, block_type, state)?;
+        loaded.remesh(&self.renderer, &self.arena);
+        if let Some(eye) = self.sorted_for {
+            loaded.sort_translucent(&self.renderer, key, eye);
+        }
+        Ok(())
     }
 }
 
@@ -56,18 +310,12 @@ impl EventListener for World {
         match event {
             InputEvent::MouseClicked(button) => {
                 // And maybe place a block
-                if let Some((pos, face)) = self.last_picked.as_ref() {
-                    // TODO: find chunk based on pos
-                    let (_, (chunk, mesh)) = self.chunks.iter_mut().next().unwrap();
-
-                    let recompute = match button {
+                if let Some((pos, face)) = self.last_picked.clone() {
+                    match button {
                         Button::Primary => {
                             // Set the currently selected block to be air
-
-                            chunk
-                                .set(pos.as_ivec3(), BlockType::Air)
+                            self.set(pos.as_ivec3(), BlockType::Air, BlockState::default())
                                 .expect("failed to set air");
-                            true
                         }
                         Button::Secondary => {
                             // Add a block in the direction of the face
@@ -76,16 +324,14 @@ impl EventListener for World {
                                 .types
                                 .get(self.active_type)
                                 .unwrap_or(&BlockType::Stone);
-                            chunk.set(pos, block_type).is_ok()
+                            // This might be outside of the world (eg. above the build height)
+                            let state = BlockState {
+                                facing: self.facing,
+                            };
+                            let _ = self.set(pos, block_type, state);
                         }
-                        _ => false,
+                        _ => (),
                     };
-                    if recompute {
-                        *mesh = self
-                            .renderer
-                            .create_mesh(&chunk.chunk_vertices())
-                            .expect("failed to create mesh");
-                    }
                 }
             }
             InputEvent::KeyDown(key) => match key {
@@ -103,11 +349,3 @@ impl EventListener for World {
         }
     }
 }
-
-impl Drop for World {
-    fn drop(&mut self) {
-        for (_, (_, mesh)) in self.chunks.drain() {
-            self.renderer.destroy_mesh(mesh);
-        }
-    }
-}
@@ -0,0 +1,247 @@
+//! Chunk visibility graph, for occlusion culling in the style of "cave culling", eg.
+//! https://tomcc.github.io/2014/08/31/visibility-1.html
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::render::mesh::Face;
+
+use super::{
+    block::BlockType,
+    chunk::{Chunk, CHUNK_SIZE},
+};
+
+/// Which faces of a chunk are connected through non-opaque blocks, eg. whether one can see
+/// out of one face when looking into the chunk through another one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visibility(u64);
+
+impl Visibility {
+    pub const NONE: Self = Self(0);
+
+    fn bit(a: &Face, b: &Face) -> u64 {
+        1 << (a.index() * 6 + b.index())
+    }
+
+    pub fn connect(&mut self, a: &Face, b: &Face) {
+        self.0 |= Self::bit(a, b) | Self::bit(b, a);
+    }
+
+    pub fn connected(&self, a: &Face, b: &Face) -> bool {
+        self.0 & Self::bit(a, b) != 0
+    }
+
+    /// Flood fills all regions of non-opaque blocks and connects the faces each region touches
+    pub fn compute(chunk: &Chunk) -> Self {
+        const SIZE: i32 = CHUNK_SIZE as i32;
+        let index = |p: glam::IVec3| ((p.x * SIZE + p.y) * SIZE + p.z) as usize;
+
+        let mut visibility = Self::NONE;
+        let mut visited = vec![false; CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+        let mut stack = Vec::new();
+
+        for start in Chunk::positions() {
+            if visited[index(start)] || chunk.get(start).unwrap_or(BlockType::Air).is_opaque() {
+                continue;
+            }
+
+            // The faces the current region touches
+            let mut touched = Vec::new();
+            visited[index(start)] = true;
+            stack.push(start);
+
+            while let Some(pos) = stack.pop() {
+                for face in Face::FACES.iter() {
+                    let next = pos + face.neighbor_dir();
+                    match chunk.get(next) {
+                        // Leaving the chunk through that face
+                        None => {
+                            if !touched.contains(face) {
+                                touched.push(face.clone())
+                            }
+                        }
+                        Some(block) => {
+                            if !block.is_opaque() && !visited[index(next)] {
+                                visited[index(next)] = true;
+                                stack.push(next);
+                            }
+                        }
+                    }
+                }
+            }
+
+            for a in touched.iter() {
+                for b in touched.iter() {
+                    visibility.connect(a, b);
+                }
+            }
+        }
+
+        visibility
+    }
+}
+
+/// Where the visibility search starts. The space above and below the world is not made up of
+/// chunks, but it is empty and adjacent to every chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Region {
+    Chunk(glam::IVec2),
+    Above,
+    Below,
+}
+
+/// Breadth first search through the visibility graph starting at the chunk of the camera.
+/// Returns the chunks that can possibly be seen from `camera_pos`, or None if the camera is not
+/// inside of (or above/below) a known chunk, in which case nothing should be culled.
+/// A line of sight that leaves the world upwards (or downwards) never comes back, so the space
+/// above and below is only searched from when the camera is in it
+pub fn visible_chunks(
+    chunks: &HashMap<glam::IVec2, Visibility>,
+    camera_pos: glam::Vec3,
+) -> Option<HashSet<glam::IVec2>> {
+    // Block centers are at integer coordinates
+    let block = (camera_pos + 0.5).floor().as_ivec3();
+    let (key, _) = Chunk::locate(block);
+
+    let start = match block.y {
+        y if y >= CHUNK_SIZE as i32 => Region::Above,
+        y if y < 0 => Region::Below,
+        _ if chunks.contains_key(&key) => Region::Chunk(key),
+        _ => return None,
+    };
+
+    let mut visible = HashSet::new();
+    // (chunk, face through which it was entered, directions taken so far as bitmask)
+    let mut queue = VecDeque::new();
+    match start {
+        Region::Chunk(key) => {
+            visible.insert(key);
+            queue.push_back((key, None, 0u8));
+        }
+        // Every chunk is entered from the open space
+        Region::Above | Region::Below => {
+            let face = if start == Region::Above {
+                Face::PositiveY
+            } else {
+                Face::NegativeY
+            };
+            for key in chunks.keys() {
+                visible.insert(*key);
+                queue.push_back((*key, Some(face.clone()), 0u8));
+            }
+        }
+    }
+
+    while let Some((key, entered, directions)) = queue.pop_front() {
+        let visibility = chunks[&key];
+        // Leaving through the top or bottom leads into the open space, where nothing is left
+        for face in Face::FACES.iter().filter(|f| f.neighbor_dir().y == 0) {
+            // We never go back into a direction we came from
+            if directions & (1 << face.opposite().index()) != 0 {
+                continue;
+            }
+            if let Some(entered) = &entered {
+                if !visibility.connected(entered, face) {
+                    continue;
+                }
+            }
+
+            let dir = face.neighbor_dir();
+            let next = key + glam::ivec2(dir.x, dir.z);
+            if chunks.contains_key(&next) && visible.insert(next) {
+                let directions = directions | (1 << face.index());
+                queue.push_back((next, Some(face.opposite()), directions));
+            }
+        }
+    }
+
+    Some(visible)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// A chunk whose terrain is flat at `height` (from -1 to 1, relative to the chunk size)
+    fn flat(height: f64) -> Chunk {
+        let mut rng = StdRng::seed_from_u64(0);
+        Chunk::new(glam::IVec2::ZERO, &noise::Constant::new(height), &mut rng)
+    }
+
+    fn solid() -> Chunk {
+        flat(1.0)
+    }
+
+    fn open() -> Visibility {
+        Visibility::compute(&flat(-3.0))
+    }
+
+    /// A solid chunk with a straight tunnel from its negative to its positive x face
+    fn tunnel() -> Chunk {
+        let mut chunk = solid();
+        for x in 0..CHUNK_SIZE as i32 {
+            let pos = glam::ivec3(x, 8, 8);
+            chunk.set(pos, BlockType::Air, Default::default()).unwrap();
+        }
+        chunk
+    }
+
+    /// Chunks in a row along the x axis, starting at the key 0, 0
+    fn row(visibilities: &[Visibility]) -> HashMap<glam::IVec2, Visibility> {
+        visibilities
+            .iter()
+            .enumerate()
+            .map(|(x, v)| (glam::ivec2(x as i32, 0), *v))
+            .collect()
+    }
+
+    fn keys(xs: &[i32]) -> Option<HashSet<glam::IVec2>> {
+        Some(xs.iter().map(|x| glam::ivec2(*x, 0)).collect())
+    }
+
+    #[test]
+    fn open_chunks_connect_every_face() {
+        let visibility = open();
+        for a in Face::FACES.iter() {
+            for b in Face::FACES.iter() {
+                assert!(visibility.connected(a, b));
+            }
+        }
+    }
+
+    #[test]
+    fn sealed_chunks_connect_nothing() {
+        assert_eq!(Visibility::compute(&solid()), Visibility::NONE);
+    }
+
+    #[test]
+    fn tunnels_connect_their_ends() {
+        let visibility = Visibility::compute(&tunnel());
+        assert!(visibility.connected(&Face::NegativeX, &Face::PositiveX));
+        assert!(!visibility.connected(&Face::NegativeX, &Face::PositiveY));
+        assert!(!visibility.connected(&Face::NegativeZ, &Face::PositiveZ));
+    }
+
+    #[test]
+    fn sealed_chunks_block_the_view() {
+        let camera = glam::vec3(8.0, 8.0, 8.0);
+        let chunks = row(&[open(), Visibility::NONE, open()]);
+        // The sealed chunk itself might be seen, but not what is behind it
+        assert_eq!(visible_chunks(&chunks, camera), keys(&[0, 1]));
+
+        let chunks = row(&[open(), Visibility::compute(&tunnel()), open()]);
+        assert_eq!(visible_chunks(&chunks, camera), keys(&[0, 1, 2]));
+    }
+
+    #[test]
+    fn the_ground_is_seen_from_above() {
+        let camera = glam::vec3(8.0, CHUNK_SIZE as f32 + 4.0, 8.0);
+        let chunks = row(&[open(), Visibility::NONE, open()]);
+        assert_eq!(visible_chunks(&chunks, camera), keys(&[0, 1, 2]));
+    }
+
+    #[test]
+    fn unknown_chunks_cull_nothing() {
+        let chunks = row(&[open()]);
+        assert_eq!(visible_chunks(&chunks, glam::vec3(-8.0, 8.0, 8.0)), None);
+    }
+}
@@ -0,0 +1,465 @@
+//! Golden image tests, that render fixed scenes headless (eg. with Mesa's software rasteriser)
+//! and compare them to the reference images in `tests/golden`.
+//!
+//! ```text
+//! cargo test --features native --test golden
+//! UPDATE_GOLDEN=1 cargo test --features native --test golden
+//! ```
+//!
+//! The second one (re)writes the references, on a mismatch the rendered image and the
+//! differences are written to the target directory. With the `dev` feature, reloading the
+//! shaders is tested as well
+use std::{path::PathBuf, rc::Rc};
+
+use rust_404::{
+    atlas::{builder::Image, TextureBackend},
+    platform::{
+        native::{FileImageLoader, HeadlessContext},
+        png::{read_png, write_png},
+    },
+    render::{
+        camera::Camera, cube, errors, inventory, material::STANDARD_VERTEX_SHADER,
+        mesh::build_selection_ring, mesh::selection_ring_transform, pick_face, sky::Sky, Instance,
+        Material, Mesh, MeshArena, RenderLayer, Renderer,
+    },
+    world::{BlockState, BlockType, Facing, World, CHUNK_SIZE},
+};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+const SEED: u32 = 404;
+
+/// Pixels whose perceptual difference (in [0; 1]) is above this count as different
+const PIXEL_THRESHOLD: f32 = 0.1;
+/// Fraction of the pixels that may differ, eg. due to another rasteriser
+const MAX_DIFFERENT: f32 = 0.005;
+/// The same for supersampled frames compared to direct ones, whose thin cutout geometry (eg.
+/// plants) and edges are antialiased
+const MAX_DIFFERENT_SUPERSAMPLED: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Overlay {
+    None,
+    SelectionRing,
+    Inventory,
+    /// A grid of differently colored cubes in front of the camera
+    Instances,
+    /// Shader errors, as shown after a failed reload
+    Errors,
+}
+
+/// A seeded world seen from a fixed pose at a fixed time of day (eg. a fixed `light_dir`)
+struct Scene {
+    world: World,
+    selection_ring: Mesh,
+    cube: Mesh,
+    camera: Camera,
+    sky: Sky,
+    renderer: Rc<Renderer>,
+    // Has to outlive everything that uses the context
+    _context: HeadlessContext,
+}
+
+impl Scene {
+    fn new() -> Self {
+        let (context, surface) =
+            HeadlessContext::new(WIDTH, HEIGHT).expect("failed to create headless context");
+        let images = FileImageLoader::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../../public"));
+        let renderer = Rc::new(
+            futures::executor::block_on(Renderer::new(
+                surface,
+                TextureBackend::Atlas,
+                Box::new(images),
+            ))
+            .expect("failed to create renderer"),
+        );
+
+        let mut camera = Camera::new();
+        camera.set_aspect(WIDTH as f32 / HEIGHT as f32);
+        camera.set_pose(glam::vec3(-6.0, 22.0, -6.0), 45.0, -30.0);
+
+        let mut world = World::with_seed(renderer.clone(), SEED);
+        world.update(&camera);
+
+        Self {
+            world,
+            selection_ring: renderer
+                .create_mesh(&build_selection_ring())
+                .expect("failed to create selection ring mesh"),
+            cube: renderer
+                .create_mesh(&cube(glam::Vec3::ONE, &renderer.texture_layout()))
+                .expect("failed to create cube mesh"),
+            camera,
+            sky: Sky::new(),
+            renderer,
+            _context: context,
+        }
+    }
+
+    /// Height of the highest block at `x`, `z`
+    fn top(&self, x: i32, z: i32) -> i32 {
+        (0..CHUNK_SIZE as i32)
+            .rev()
+            .find(|y| {
+                matches!(self.world.block(glam::ivec3(x, *y, z)), Some(block) if block != BlockType::Air)
+            })
+            .expect("no ground to look at")
+    }
+
+    /// Moves the camera close to the ground, looking down onto the block at `x`, `z`
+    fn close_up(&mut self, x: i32, z: i32) {
+        let top = self.top(x, z);
+        let pos = glam::vec3(x as f32 - 1.5, top as f32 + 2.5, z as f32 - 1.5);
+        self.camera.set_pose(pos, 45.0, -50.0);
+        self.world.update(&self.camera);
+    }
+
+    fn render(&self, overlay: Overlay) -> Image {
+        self.capture(overlay, 1)
+    }
+
+    /// Renders at `scale` times the size of the surface
+    fn capture(&self, overlay: Overlay, scale: u32) -> Image {
+        self.renderer
+            .capture(scale, |_| self.draw(overlay))
+            .expect("failed to read back the frame")
+    }
+
+    fn draw(&self, overlay: Overlay) {
+        let (mut task, mut frame) = self.renderer.start_frame(&self.camera);
+        self.world.render(&mut task, &self.camera);
+
+        match overlay {
+            Overlay::None => {}
+            Overlay::SelectionRing => {
+                let loc = self
+                    .renderer
+                    .pick(&task, &self.camera)
+                    .expect("nothing in the center of the screen");
+                let boxes = self
+                    .world
+                    .boxes(loc.as_ivec3())
+                    .expect("picked block is not loaded");
+                let (face, bounds) =
+                    pick_face(&self.camera, loc, &boxes).expect("no face of the block is visible");
+                task.push_with_transform_and_material(
+                    &self.selection_ring,
+                    selection_ring_transform(loc, &face, &bounds),
+                    Material::solid(glam::vec4(0.7, 0.7, 0.7, 1.0)),
+                );
+            }
+            Overlay::Inventory => inventory(
+                &mut frame,
+                self.world.types(),
+                self.world.active_type(),
+                &self.renderer.get_atlas(),
+                &self.renderer.texture_layout(),
+                self.renderer.viewport().logical_size(),
+            ),
+            Overlay::Errors => errors(
+                &mut frame,
+                &[
+                    "solid.vert + solid.frag: 0:12(3): error: `colour' undeclared".to_string(),
+                    "Sky.frag: unknown include \"noise.glsl\"\nsecond line".to_string(),
+                ],
+                self.renderer.viewport().logical_size(),
+            ),
+            Overlay::Instances => {
+                // Roughly 10 blocks in front of the camera, the first row is textured and the
+                // last one translucent
+                let center = glam::vec3(1.0, 17.0, 1.0);
+                let (atlas, solid) = (Material::atlas(), Material::solid(glam::Vec4::ONE));
+                for x in 0..4 {
+                    for z in 0..4 {
+                        let offset = glam::vec3(x as f32 - 1.5, 0.0, z as f32 - 1.5);
+                        let (material, color) = match z {
+                            0 => (&atlas, glam::Vec4::ONE),
+                            3 => (&solid, glam::vec4(0.2, 0.4, 0.9, 0.5)),
+                            _ => (&solid, glam::vec4(x as f32 / 3.0, z as f32 / 3.0, 0.2, 1.0)),
+                        };
+                        let layer = match z {
+                            3 => RenderLayer::Translucent,
+                            _ => RenderLayer::Opaque,
+                        };
+                        let transform = glam::Mat4::from_scale_rotation_translation(
+                            glam::Vec3::splat(0.6),
+                            glam::Quat::from_rotation_y(0.3 * x as f32),
+                            center + 1.2 * offset,
+                        );
+                        let instance = Instance { transform, color };
+                        task.push_instance(&self.cube, instance, material, layer);
+                    }
+                }
+            }
+        }
+
+        self.renderer
+            .render(task, frame, &self.camera, &self.sky, 0.0);
+    }
+}
+
+/// Perceptual difference of two colors in [0; 1], eg. the distance in the YIQ color space
+/// (weighted like in "Measuring perceived color difference using YIQ NTSC transmission color
+/// space in mobile applications" by Kotsarenko and Ramos)
+fn difference(a: [u8; 4], b: [u8; 4]) -> f32 {
+    let yiq = |p: [u8; 4]| {
+        let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+        glam::vec3(
+            0.298_895_3 * r + 0.586_622_5 * g + 0.114_482_2 * b,
+            0.595_978 * r - 0.274_176_1 * g - 0.321_801_9 * b,
+            0.211_470_2 * r - 0.522_617_1 * g + 0.311_146_9 * b,
+        )
+    };
+    let d = yiq(a) - yiq(b);
+    let delta = 0.5053 * d.x * d.x + 0.299 * d.y * d.y + 0.1957 * d.z * d.z;
+    // the largest possible delta, eg. between black and white
+    (delta / 35215.0).sqrt()
+}
+
+fn assert_matches_golden(name: &str, actual: &Image) {
+    assert_matches_golden_within(name, actual, MAX_DIFFERENT);
+}
+
+/// Like `assert_matches_golden`, but at most `max_different` of the pixels may differ
+fn assert_matches_golden_within(name: &str, actual: &Image, max_different: f32) {
+    let golden_dir = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden"));
+    let golden_path = golden_dir.join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        write_png(&golden_path, actual).expect("failed to write golden image");
+        return;
+    }
+
+    let golden = read_png(&golden_path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read {} (run with UPDATE_GOLDEN=1 to create it): {}",
+            golden_path.display(),
+            e
+        )
+    });
+    assert_eq!(
+        (golden.width, golden.height),
+        (actual.width, actual.height),
+        "{} has a different size",
+        name
+    );
+
+    // Differences are marked red on a faded copy of the golden image
+    let mut diff = Image::new(golden.width, golden.height);
+    let mut different = 0;
+    for y in 0..golden.height {
+        for x in 0..golden.width {
+            let (g, a) = (golden.pixel(x, y), actual.pixel(x, y));
+            if difference(g, a) > PIXEL_THRESHOLD {
+                different += 1;
+                diff.set_pixel(x, y, [255, 0, 0, 255]);
+            } else {
+                let faded = |c: u8| 192 + c / 4;
+                diff.set_pixel(x, y, [faded(g[0]), faded(g[1]), faded(g[2]), 255]);
+            }
+        }
+    }
+
+    let fraction = different as f32 / (golden.width * golden.height) as f32;
+    if fraction > max_different {
+        let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+        std::fs::create_dir_all(&out_dir).expect("failed to create output directory");
+        let actual_path = out_dir.join(format!("{}.actual.png", name));
+        let diff_path = out_dir.join(format!("{}.diff.png", name));
+        write_png(&actual_path, actual).expect("failed to write rendered image");
+        write_png(&diff_path, &diff).expect("failed to write difference image");
+        panic!(
+            "{}: {:.2}% of the pixels differ (at most {:.2}% may), see {} and {}",
+            name,
+            100.0 * fraction,
+            100.0 * max_different,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+fn world() {
+    let scene = Scene::new();
+    assert_matches_golden("world", &scene.render(Overlay::None));
+}
+
+#[test]
+fn selection_ring() {
+    let mut scene = Scene::new();
+    scene.close_up(4, 4);
+    assert_matches_golden("selection_ring", &scene.render(Overlay::SelectionRing));
+}
+
+#[test]
+fn updated_meshes() {
+    let mut scene = Scene::new();
+    scene.close_up(4, 4);
+
+    // The ring does not fit into the range of the cube, so it moves within the arena
+    let arena = MeshArena::new(1024);
+    let mut mesh = scene
+        .renderer
+        .create_mesh_in(
+            &arena,
+            &cube(glam::Vec3::ONE, &scene.renderer.texture_layout()),
+        )
+        .expect("failed to create mesh in arena");
+    scene
+        .renderer
+        .update_mesh(&mut mesh, &build_selection_ring())
+        .expect("failed to update mesh");
+    assert_eq!(arena.pages(), 1);
+    scene.selection_ring = mesh;
+    assert_matches_golden("selection_ring", &scene.render(Overlay::SelectionRing));
+
+    // A mesh with its own buffer grows it
+    scene
+        .renderer
+        .update_mesh(&mut scene.cube, &build_selection_ring())
+        .expect("failed to update mesh");
+    std::mem::swap(&mut scene.selection_ring, &mut scene.cube);
+    assert_matches_golden("selection_ring", &scene.render(Overlay::SelectionRing));
+}
+
+#[test]
+fn grown_meshes_give_back_their_range() {
+    let scene = Scene::new();
+    let cubes = |n| cube(glam::Vec3::ONE, &scene.renderer.texture_layout()).repeat(n);
+
+    let arena = MeshArena::new(1024);
+    let mut mesh = scene
+        .renderer
+        .create_mesh_in(&arena, &cubes(8))
+        .expect("failed to create mesh in arena");
+    // Moves behind its previous range, which leaves too little room at the end of the page
+    scene
+        .renderer
+        .update_mesh(&mut mesh, &cubes(10))
+        .expect("failed to update mesh");
+
+    // So this only fits into the page, if the previous range has been given back
+    let _other = scene
+        .renderer
+        .create_mesh_in(&arena, &cubes(7))
+        .expect("failed to create mesh in arena");
+    assert_eq!(arena.pages(), 1);
+}
+
+#[test]
+fn rotated_shapes() {
+    let mut scene = Scene::new();
+
+    // Stairs facing each direction, seen from above
+    let facings = [
+        Facing::PositiveZ,
+        Facing::PositiveX,
+        Facing::NegativeZ,
+        Facing::NegativeX,
+    ];
+    for (facing, (x, z)) in facings.iter().zip([(4, 4), (6, 4), (4, 6), (6, 6)]) {
+        let pos = glam::ivec3(x, scene.top(x, z) + 1, z);
+        let state = BlockState { facing: *facing };
+        scene
+            .world
+            .set(pos, BlockType::WoodStairs, state)
+            .expect("failed to place stairs");
+    }
+    let top = scene.top(5, 5) as f32;
+    scene
+        .camera
+        .set_pose(glam::vec3(1.0, top + 6.0, 1.0), 45.0, -50.0);
+    assert_matches_golden("rotated_shapes", &scene.render(Overlay::None));
+}
+
+#[test]
+fn inventory_ui() {
+    let scene = Scene::new();
+    assert_matches_golden("inventory", &scene.render(Overlay::Inventory));
+}
+
+#[test]
+fn shader_errors() {
+    let scene = Scene::new();
+    assert_matches_golden("shader_errors", &scene.render(Overlay::Errors));
+}
+
+#[test]
+fn instances() {
+    let scene = Scene::new();
+    assert_matches_golden("instances", &scene.render(Overlay::Instances));
+}
+
+/// Averages each `scale` x `scale` block of pixels
+fn downsample(image: &Image, scale: u32) -> Image {
+    let (width, height) = (image.width / scale, image.height / scale);
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0u32; 4];
+            for (dx, dy) in (0..scale).flat_map(|dx| (0..scale).map(move |dy| (dx, dy))) {
+                let i = (((y * scale + dy) * image.width + x * scale + dx) * 4) as usize;
+                for (s, p) in sum.iter_mut().zip(&image.pixels[i..i + 4]) {
+                    *s += *p as u32;
+                }
+            }
+            pixels.extend(sum.iter().map(|s| (s / (scale * scale)) as u8));
+        }
+    }
+    Image::from_rgba(width, height, pixels).unwrap()
+}
+
+#[test]
+fn supersampled_capture() {
+    let scene = Scene::new();
+    let image = scene.capture(Overlay::Inventory, 2);
+    assert_eq!((image.width, image.height), (2 * WIDTH, 2 * HEIGHT));
+    assert_matches_golden_within(
+        "inventory",
+        &downsample(&image, 2),
+        MAX_DIFFERENT_SUPERSAMPLED,
+    );
+
+    // The surface is rendered to at its own size again afterwards
+    assert_matches_golden("inventory", &scene.render(Overlay::Inventory));
+}
+
+#[test]
+fn custom_programs_are_cached() {
+    let scene = Scene::new();
+    let selection = include_str!("../src/render/shaders/selection.frag");
+    let program = scene
+        .renderer
+        .create_program(STANDARD_VERTEX_SHADER, selection)
+        .expect("failed to compile the selection material");
+    assert_eq!(
+        scene
+            .renderer
+            .create_program(STANDARD_VERTEX_SHADER, selection)
+            .unwrap(),
+        program
+    );
+    assert!(scene
+        .renderer
+        .create_program(STANDARD_VERTEX_SHADER, "#version 300 es\nnot glsl")
+        .is_err());
+}
+
+#[cfg(feature = "dev")]
+#[test]
+fn broken_shaders_keep_the_previous_program() {
+    use rust_404::platform::native::FileTextLoader;
+
+    let scene = Scene::new();
+    let loader = FileTextLoader::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src/render/shaders"));
+    let mut sources = futures::executor::block_on(Renderer::fetch_shaders(&loader))
+        .expect("failed to load the shaders");
+
+    scene.renderer.reload_shaders(&sources);
+    assert_eq!(scene.renderer.shader_errors(), Vec::<String>::new());
+
+    sources.insert("solid.frag".into(), "#version 300 es\nnot glsl".into());
+    scene.renderer.reload_shaders(&sources);
+    assert!(!scene.renderer.shader_errors().is_empty());
+    assert_matches_golden("world", &scene.render(Overlay::None));
+}
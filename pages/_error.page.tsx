@@ -3,6 +3,9 @@ import init, { Game } from 'rust-404';
 
 export { Page };
 
+const WIDTH = 600;
+const HEIGHT = 400;
+
 const renderLoop = (g: Game, last: number) => {
     let now = window.performance.now();
     g.update((now - last) / 1000.0, now / 1000.0);
@@ -20,8 +23,16 @@ const Page404: FC = () => {
     }, []);
 
     useEffect(() => {
+        if (game === undefined) return;
+
+        // Render at the full resolution of the display, the device pixel ratio changes eg. when zooming
+        const onResize = () => game.resize(WIDTH, HEIGHT, window.devicePixelRatio);
+        onResize();
+        window.addEventListener('resize', onResize);
+
         return () => {
-            if (game !== undefined) game.free();
+            window.removeEventListener('resize', onResize);
+            game.free();
         };
     }, [game]);
 
@@ -52,7 +63,14 @@ const Page404: FC = () => {
                     'rounded-xl shadow-dark-50 shadow-md from-green-200 to-blue-100 bg-gradient-to-l p-4 dark:bg-gray-800'
                 }
             >
-                <canvas id={'canvas'} width={600} height={400} className={''} onClick={onClick} />
+                <canvas
+                    id={'canvas'}
+                    width={WIDTH}
+                    height={HEIGHT}
+                    style={{ width: WIDTH, height: HEIGHT }}
+                    className={''}
+                    onClick={onClick}
+                />
             </div>
         </div>
     );